@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+    key TEXT PRIMARY KEY,
+    full_at TIMESTAMPTZ NOT NULL
+);
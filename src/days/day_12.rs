@@ -1,10 +1,28 @@
-use std::{fmt::Display, sync::RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::{LazyLock, RwLock},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use salvo::{
-    oapi::{extract::PathParam, BasicType, Content, Object, Schema},
+    oapi::{
+        extract::{PathParam, QueryParam},
+        BasicType, Content, Object, Schema,
+    },
     prelude::*,
+    sse::{SseEvent, SseKeepAlive},
+    websocket::{Message, WebSocket, WebSocketUpgrade},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use uuid::Uuid;
+
+use crate::rate_limit::{LimitConfig, RateLimited, RateLimiter};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Item {
@@ -27,13 +45,24 @@ impl Display for Item {
     }
 }
 
+impl Item {
+    fn team(self) -> Option<Team> {
+        match self {
+            Self::Empty => None,
+            Self::Cookie => Some(Team::Cookie),
+            Self::Milk => Some(Team::Milk),
+        }
+    }
+}
+
 impl Default for Item {
     fn default() -> Self {
         Self::Empty
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
 enum GameState {
     NotEnded,
     CookieWon,
@@ -60,80 +89,124 @@ impl From<Item> for GameState {
     }
 }
 
+/// Largest number of rows or columns a board can have.
+const MAX_BOARD_SIZE: usize = 10;
+
+/// Shape of a board and how many in a row it takes to win, chosen when creating a game.
+#[derive(Debug, Clone, Copy, Deserialize, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+#[serde(default)]
+struct BoardSize {
+    /// Number of rows, 4 by default
+    rows: usize,
+    /// Number of columns, 4 by default
+    columns: usize,
+    /// Pieces in a row needed to win, 4 by default
+    win_length: usize,
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        Self {
+            rows: 4,
+            columns: 4,
+            win_length: 4,
+        }
+    }
+}
+
+impl BoardSize {
+    fn validate(self) -> Result<Self, GameError> {
+        if !(1..=MAX_BOARD_SIZE).contains(&self.rows)
+            || !(1..=MAX_BOARD_SIZE).contains(&self.columns)
+        {
+            return Err(GameError::InvalidSize(format!(
+                "rows and columns must be between 1 and {MAX_BOARD_SIZE}"
+            )));
+        }
+        if self.win_length == 0 || self.win_length > self.rows.max(self.columns) {
+            return Err(GameError::InvalidSize(
+                "win length must fit on the board".to_owned(),
+            ));
+        }
+        Ok(self)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Board {
-    board: [[Item; 4]; 4],
+    board: Vec<Vec<Item>>,
+    win_length: usize,
     state: GameState,
+    /// Team that placed the last piece, if the board was built move by move
+    last_team: Option<Team>,
+    /// Whether teams must take turns
+    strict: bool,
 }
 
 impl Board {
-    const fn new() -> Self {
+    fn new(size: BoardSize) -> Self {
         Self {
-            board: [
-                [Item::Empty, Item::Empty, Item::Empty, Item::Empty],
-                [Item::Empty, Item::Empty, Item::Empty, Item::Empty],
-                [Item::Empty, Item::Empty, Item::Empty, Item::Empty],
-                [Item::Empty, Item::Empty, Item::Empty, Item::Empty],
-            ],
+            board: vec![vec![Item::Empty; size.columns]; size.rows],
+            win_length: size.win_length,
             state: GameState::NotEnded,
+            last_team: None,
+            strict: false,
         }
     }
 
-    fn check_win(&mut self) {
-        // horizontal win
-        for row in self.board {
-            let first = row[0];
-            let all_same = row.iter().all(|v| *v == first);
-            if all_same {
-                self.state = first.into();
-                if self.state.is_game_over() {
-                    return;
-                }
-            }
+    fn winner(&self) -> Option<Team> {
+        match self.state {
+            GameState::CookieWon => Some(Team::Cookie),
+            GameState::MilkWon => Some(Team::Milk),
+            GameState::NotEnded | GameState::NoWinner => None,
         }
+    }
 
-        // vertical win
-        for col_index in 0..4 {
-            let first = self.board[0][col_index];
-            let all_same = self
-                .board
-                .iter()
-                .map(|row| row[col_index])
-                .all(|v| v == first);
-            if all_same {
-                self.state = first.into();
-                if self.state.is_game_over() {
-                    return;
-                }
-            }
+    fn columns(&self) -> usize {
+        self.board[0].len()
+    }
+
+    fn size(&self) -> BoardSize {
+        BoardSize {
+            rows: self.board.len(),
+            columns: self.columns(),
+            win_length: self.win_length,
         }
+    }
 
-        // main diagonal win
-        let first = self.board[0][0];
-        let all_same = self
-            .board
-            .iter()
-            .enumerate()
-            .skip(1)
-            .all(|(row_index, row)| row[row_index] == first);
-        if all_same {
-            self.state = first.into();
-            if self.state.is_game_over() {
-                return;
-            }
+    /// Whether `win_length` pieces of the same team start at `(row, column)` and go in the
+    /// direction `(row_step, column_step)`.
+    fn line_from(&self, row: usize, column: usize, row_step: isize, column_step: isize) -> bool {
+        let first = self.board[row][column];
+        if first == Item::Empty {
+            return false;
         }
+        (1..self.win_length).all(|step| {
+            let row = row.checked_add_signed(row_step * step as isize);
+            let column = column.checked_add_signed(column_step * step as isize);
+            match (row, column) {
+                (Some(row), Some(column)) => self
+                    .board
+                    .get(row)
+                    .and_then(|items| items.get(column))
+                    .is_some_and(|item| *item == first),
+                _ => false,
+            }
+        })
+    }
 
-        let first = self.board[0][3];
-        let all_same = self
-            .board
-            .iter()
-            .enumerate()
-            .skip(1)
-            .all(|(row_index, row)| row[3 - row_index] == first);
-        if all_same {
-            self.state = first.into();
-            if self.state.is_game_over() {
-                return;
+    fn check_win(&mut self) {
+        // horizontal, then vertical, then both diagonals, so a board with lines for both teams
+        // is always given to the same one
+        for (row_step, column_step) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for row in 0..self.board.len() {
+                for column in 0..self.columns() {
+                    if self.line_from(row, column, row_step, column_step) {
+                        self.state = self.board[row][column].into();
+                        return;
+                    }
+                }
             }
         }
 
@@ -146,8 +219,25 @@ impl Board {
         }
     }
 
-    pub fn place(&mut self, team: Team, column: usize) -> Result<(), PlaceError> {
-        if column > 3 {
+    /// A full board of cookies and milk drawn from `rng`, row by row.
+    fn random(size: BoardSize, rng: &mut StdRng) -> Self {
+        let mut board = Self::new(size);
+        for row in board.board.iter_mut() {
+            for item in row.iter_mut() {
+                *item = if rng.gen::<bool>() {
+                    Item::Cookie
+                } else {
+                    Item::Milk
+                };
+            }
+        }
+        board.check_win();
+        board
+    }
+
+    /// Drops a piece into `column`, returning the row it landed in.
+    pub fn place(&mut self, team: Team, column: usize) -> Result<usize, PlaceError> {
+        if column >= self.columns() {
             return Err(PlaceError::ColumnNotFound);
         }
 
@@ -168,28 +258,120 @@ impl Board {
         match row_index {
             Some(row_index) => {
                 self.board[row_index][column] = team.into();
+                self.last_team = Some(team);
                 self.check_win();
-                Ok(())
+                Ok(row_index)
             }
             None => Err(PlaceError::ColumnFull(self.clone())),
         }
     }
 }
 
+/// Score of a won game. Quicker wins score higher, by the search depth left when they happen.
+const WIN_SCORE: i32 = 1000;
+
+/// Search depth for `/12/ai` when none is given, and the most allowed.
+const DEFAULT_AI_DEPTH: u32 = 6;
+const MAX_AI_DEPTH: u32 = 10;
+
+/// Most positions a search may reach, before pruning, which limits the depth on wide boards.
+const AI_NODE_BUDGET: u64 = 2_000_000;
+
+/// `AI_BUCKET_CAPACITY` and `AI_REFILL_INTERVAL_MS`, shared by every game since each search
+/// ties up a blocking thread.
+static AI_LIMITER: LazyLock<RateLimiter> = LazyLock::new(|| {
+    let limit = LimitConfig::from_env("AI", 5, Duration::from_secs(1));
+    RateLimiter::new("ai", limit.capacity, limit.refill_interval)
+});
+
+/// Deepest search that stays within [`AI_NODE_BUDGET`] with `columns` moves per turn.
+fn ai_depth_limit(columns: usize) -> u32 {
+    let columns = columns.max(2) as u64;
+    let mut depth = 1;
+    let mut nodes = columns;
+    while depth < MAX_AI_DEPTH && nodes.saturating_mul(columns) <= AI_NODE_BUDGET {
+        nodes *= columns;
+        depth += 1;
+    }
+    depth
+}
+
+/// Computer opponent: a depth-limited minimax with alpha-beta pruning, in negamax form. Moves are
+/// tried with [`Board::place`] so the search follows the same rules as the game.
+impl Board {
+    /// Score of the board for `team`, who is about to move.
+    fn negamax(&self, team: Team, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+        if self.state.is_game_over() {
+            let score = WIN_SCORE + depth as i32;
+            return match self.winner() {
+                Some(winner) if winner == team => score,
+                Some(_) => -score,
+                None => 0,
+            };
+        }
+        if depth == 0 {
+            return 0;
+        }
+
+        let mut best = -(WIN_SCORE + MAX_AI_DEPTH as i32 + 1);
+        for column in 0..self.columns() {
+            let mut next = self.clone();
+            if next.place(team, column).is_err() {
+                continue;
+            }
+            let score = -next.negamax(team.other(), depth - 1, -beta, -alpha);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    /// The best column for `team` looking `depth` moves ahead, with its score. Positive scores
+    /// mean `team` can force a win, negative ones that the other team can.
+    fn best_move(&self, team: Team, depth: u32) -> Option<(usize, i32)> {
+        let bound = WIN_SCORE + MAX_AI_DEPTH as i32 + 1;
+        let mut alpha = -bound;
+        let mut best = None;
+        for column in 0..self.columns() {
+            let mut next = self.clone();
+            if next.place(team, column).is_err() {
+                continue;
+            }
+            let score = -next.negamax(team.other(), depth.saturating_sub(1), -bound, -alpha);
+            let better = match best {
+                Some((_, best_score)) => score > best_score,
+                None => true,
+            };
+            if better {
+                best = Some((column, score));
+                alpha = alpha.max(score);
+            }
+        }
+        best
+    }
+}
+
 impl Display for Board {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         const WALL: &str = "⬜";
 
         let out = self
             .board
+            .iter()
             .map(|row| {
                 format!(
                     "{WALL}{}{WALL}",
-                    row.map(|state| state.to_string()).join(""),
+                    row.iter()
+                        .map(|state| state.to_string())
+                        .collect::<String>(),
                 )
             })
+            .collect::<Vec<_>>()
             .join("\n");
-        let out = format!("{}\n{}\n", out, WALL.repeat(6));
+        let out = format!("{}\n{}\n", out, WALL.repeat(self.columns() + 2));
         let out = match self.state {
             GameState::NotEnded => out,
             GameState::CookieWon => format!("{}{}\n", out, "🍪 wins!"),
@@ -200,51 +382,633 @@ impl Display for Board {
     }
 }
 
+/// Board as sent to clients asking for `application/json`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+struct BoardView {
+    /// Rows from top to bottom, with `null` for empty cells
+    cells: Vec<Vec<Option<Team>>>,
+    win_length: usize,
+    state: GameState,
+    winner: Option<Team>,
+    /// Team that must move next. `null` if turns aren't enforced, either team may move, or the
+    /// game is over.
+    next_player: Option<Team>,
+}
+
+impl From<&Board> for BoardView {
+    fn from(board: &Board) -> Self {
+        Self::new(board, board.strict)
+    }
+}
+
+impl BoardView {
+    /// The board as seen by a client that must take turns if `enforce_turns` is set.
+    fn new(board: &Board, enforce_turns: bool) -> Self {
+        Self {
+            cells: board
+                .board
+                .iter()
+                .map(|row| row.iter().map(|item| item.team()).collect())
+                .collect(),
+            win_length: board.win_length,
+            state: board.state,
+            winner: board.winner(),
+            next_player: if !enforce_turns || board.state.is_game_over() {
+                None
+            } else {
+                board.last_team.map(Team::other)
+            },
+        }
+    }
+}
+
 #[async_trait]
 impl Writer for Board {
+    async fn write(self, req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+        let wants_json = req
+            .accept()
+            .iter()
+            .any(|mime| mime.essence_str() == "application/json");
+        if wants_json {
+            res.render(Json(BoardView::from(&self)));
+        } else {
+            res.render(Text::Plain(self.to_string()));
+        }
+    }
+}
+
+impl EndpointOutRegister for Board {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        operation.responses.insert(
+            StatusCode::OK.as_str(),
+            salvo::oapi::Response::new("success")
+                .add_content(
+                    "text/plain",
+                    Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+                )
+                .add_content(
+                    "application/json",
+                    Content::new(BoardView::to_schema(components)),
+                ),
+        );
+    }
+}
+
+const RANDOM_SEED: u64 = 2024;
+
+/// Board updates buffered per spectator before they start missing some.
+const EVENTS_CAPACITY: usize = 16;
+
+/// Games not played for this long are removed.
+const GAME_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most games kept at once, not counting the default one. Creating more fails until some expire.
+const MAX_GAMES: usize = 1000;
+
+/// The game behind the legacy `/12/...` routes. It never expires.
+const DEFAULT_GAME: Uuid = Uuid::nil();
+
+/// A piece placed on the board. Rows and columns count from 1, with row 1 at the top.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+struct Move {
+    team: Team,
+    column: usize,
+    row: usize,
+    timestamp: DateTime<Utc>,
+}
+
+/// The board along with the RNG behind `/12/random-board`, both of which `/12/reset` restores.
+#[derive(Debug)]
+struct Game {
+    board: Board,
+    seed: u64,
+    rng: StdRng,
+    history: Vec<Move>,
+    /// The board after every change, for `/12/events` and `/12/ws`
+    events: broadcast::Sender<Board>,
+    /// Teams with a player connected over `/12/ws`
+    players: HashSet<Team>,
+    last_used: Instant,
+}
+
+impl Game {
+    fn new(seed: u64, strict: bool, size: BoardSize) -> Self {
+        Self {
+            board: Board {
+                strict,
+                ..Board::new(size)
+            },
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            history: Vec::new(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            players: HashSet::new(),
+            last_used: Instant::now(),
+        }
+    }
+
+    fn reset(&mut self) {
+        let events = self.events.clone();
+        let players = std::mem::take(&mut self.players);
+        *self = Self::new(self.seed, self.board.strict, self.board.size());
+        // keep spectators subscribed and players seated across resets
+        self.events = events;
+        self.players = players;
+        self.publish();
+    }
+
+    /// Replaces the board with a random one. The sequence of boards restarts on reset.
+    fn random_board(&mut self) {
+        self.board = Board {
+            strict: self.board.strict,
+            ..Board::random(self.board.size(), &mut self.rng)
+        };
+        // the moves no longer lead to this board
+        self.history.clear();
+        self.publish();
+    }
+
+    /// Sends the board to everyone following the game.
+    fn publish(&self) {
+        // an error only means nobody is listening
+        let _ = self.events.send(self.board.clone());
+    }
+
+    /// Fails if `team` placed the last piece, unless the game is over.
+    fn check_turn(&self, team: Team) -> Result<(), PlaceError> {
+        if self.board.state.is_game_over() {
+            return Ok(());
+        }
+        match self.history.last() {
+            Some(last) if last.team == team => Err(PlaceError::NotYourTurn(last.team.other())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Places a piece and records the move, checking turns in strict games.
+    fn place(&mut self, team: Team, column: usize) -> Result<(), PlaceError> {
+        if self.board.strict {
+            self.check_turn(team)?;
+        }
+
+        let row = self.board.place(team, column)?;
+        self.history.push(Move {
+            team,
+            column: column + 1,
+            row: row + 1,
+            timestamp: Utc::now(),
+        });
+        self.publish();
+        Ok(())
+    }
+
+    /// Takes back the last move.
+    fn undo(&mut self) -> Result<(), GameError> {
+        let last = self.history.pop().ok_or(GameError::NothingToUndo)?;
+        self.board.board[last.row - 1][last.column - 1] = Item::Empty;
+        self.board.last_team = self.history.last().map(|last| last.team);
+        self.board.state = GameState::NotEnded;
+        self.board.check_win();
+        self.publish();
+        Ok(())
+    }
+
+    fn is_expired(&self, id: Uuid) -> bool {
+        id != DEFAULT_GAME && self.last_used.elapsed() > GAME_TTL
+    }
+}
+
+static GAMES: LazyLock<RwLock<HashMap<Uuid, Game>>> = LazyLock::new(|| {
+    RwLock::new(HashMap::from([(
+        DEFAULT_GAME,
+        Game::new(RANDOM_SEED, false, BoardSize::default()),
+    )]))
+});
+
+#[derive(Debug, thiserror::Error)]
+enum GameError {
+    #[error("game not found")]
+    NotFound,
+
+    #[error("no moves to undo")]
+    NothingToUndo,
+
+    #[error("invalid board size: {0}")]
+    InvalidSize(String),
+
+    #[error("too many games, try again later")]
+    TooManyGames,
+
+    #[error("internal error")]
+    InternalError,
+}
+
+#[async_trait]
+impl Writer for GameError {
     async fn write(self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+        match self {
+            Self::NotFound => {
+                res.status_code(StatusCode::NOT_FOUND);
+            }
+            Self::NothingToUndo => {
+                res.status_code(StatusCode::CONFLICT);
+            }
+            Self::InvalidSize(_) => {
+                res.status_code(StatusCode::BAD_REQUEST);
+            }
+            Self::TooManyGames => {
+                res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            Self::InternalError => {
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
         res.render(Text::Plain(self.to_string()));
     }
 }
 
-impl EndpointOutRegister for Board {
+impl EndpointOutRegister for GameError {
     fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
         operation.responses.insert(
-            StatusCode::OK.as_str(),
-            salvo::oapi::Response::new("success").add_content(
+            StatusCode::NOT_FOUND.as_str(),
+            salvo::oapi::Response::new("game not found").add_content(
                 "text/plain",
                 Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
             ),
         );
+        operation.responses.insert(
+            StatusCode::BAD_REQUEST.as_str(),
+            salvo::oapi::Response::new("invalid board size").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::CONFLICT.as_str(),
+            salvo::oapi::Response::new("move not allowed").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::SERVICE_UNAVAILABLE.as_str(),
+            salvo::oapi::Response::new("too many games").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
+            salvo::oapi::Response::new("internal error").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+    }
+}
+
+/// Runs `f` on a game, keeping it from expiring. Expired games are removed when found.
+fn with_game<T>(id: Uuid, f: impl FnOnce(&mut Game) -> T) -> Result<T, GameError> {
+    let mut games = GAMES.write().map_err(|_| GameError::InternalError)?;
+    if games.get(&id).is_some_and(|game| game.is_expired(id)) {
+        games.remove(&id);
+    }
+    let game = games.get_mut(&id).ok_or(GameError::NotFound)?;
+    game.last_used = Instant::now();
+    Ok(f(game))
+}
+
+fn board(id: Uuid) -> Result<Board, GameError> {
+    with_game(id, |game| game.board.clone())
+}
+
+fn reset(id: Uuid) -> Result<Board, GameError> {
+    with_game(id, |game| {
+        game.reset();
+        game.board.clone()
+    })
+}
+
+fn random_board(id: Uuid) -> Result<Board, GameError> {
+    with_game(id, |game| {
+        game.random_board();
+        game.board.clone()
+    })
+}
+
+/// The board now, along with a receiver for every change after it.
+fn subscribe(id: Uuid) -> Result<(Board, broadcast::Receiver<Board>), GameError> {
+    with_game(id, |game| (game.board.clone(), game.events.subscribe()))
+}
+
+/// A team's place in a game, held by its WebSocket and given up when dropped.
+struct Seat {
+    id: Uuid,
+    team: Team,
+}
+
+impl Seat {
+    /// Takes `team`'s seat, failing with `None` if another socket has it.
+    fn take(id: Uuid, team: Team) -> Result<Option<Self>, GameError> {
+        // only built once the lock is released, since dropping one takes the lock
+        if with_game(id, |game| game.players.insert(team))? {
+            Ok(Some(Self { id, team }))
+        } else {
+            Ok(None)
+        }
     }
 }
 
-static BOARD: RwLock<Board> = RwLock::new(Board::new());
+impl Drop for Seat {
+    fn drop(&mut self) {
+        // a game that is gone has no seats to give up
+        if let Ok(mut games) = GAMES.write() {
+            if let Some(game) = games.get_mut(&self.id) {
+                game.players.remove(&self.team);
+            }
+        }
+    }
+}
+
+async fn ws(
+    id: Uuid,
+    team: Team,
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    let to_status = |err| match err {
+        GameError::NotFound => StatusError::not_found(),
+        _ => StatusError::internal_server_error(),
+    };
+    let seat = Seat::take(id, team)
+        .map_err(to_status)?
+        .ok_or_else(|| StatusError::conflict().brief(format!("{team} already has a player")))?;
+    let (current, updates) = subscribe(id).map_err(to_status)?;
+    WebSocketUpgrade::new()
+        .upgrade(req, res, move |ws| play(ws, seat, current, updates))
+        .await
+}
+
+/// Streams the board to `res` as server-sent `board` events, starting with its current state.
+fn events(id: Uuid, res: &mut Response) -> Result<(), GameError> {
+    let (current, updates) = subscribe(id)?;
+    let updates = BroadcastStream::new(updates).filter_map(|update| {
+        // spectators that fall behind skip to the boards they can still get
+        update.ok()
+    });
+    let events = tokio_stream::once(current).chain(updates).map(|board| {
+        serde_json::to_string(&BoardView::from(&board))
+            .map(|board| SseEvent::default().name("board").text(board))
+    });
+    SseKeepAlive::new(events).stream(res);
+    Ok(())
+}
+
+/// Frames sent by players over `/12/ws`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// Drops a piece into a column, counting from 1
+    Place {
+        column: usize,
+    },
+    Reset,
+}
+
+/// Frames sent to players over `/12/ws`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerFrame {
+    Board {
+        board: BoardView,
+    },
+    Error {
+        error: &'static str,
+        message: String,
+    },
+}
+
+impl From<PlaceError> for ServerFrame {
+    fn from(err: PlaceError) -> Self {
+        let (error, message) = match &err {
+            PlaceError::ColumnNotFound => ("column_not_found", err.to_string()),
+            // the board itself arrives in board frames
+            PlaceError::ColumnFull(_) => ("column_full", "the column is full".to_owned()),
+            PlaceError::GameOver(_) => ("game_over", "the game is over".to_owned()),
+            PlaceError::NotYourTurn(_) => ("not_your_turn", err.to_string()),
+            PlaceError::BoardChanged => ("board_changed", err.to_string()),
+            PlaceError::GameError(GameError::NotFound) => ("game_not_found", err.to_string()),
+            PlaceError::GameError(_) => ("internal_error", err.to_string()),
+        };
+        Self::Error { error, message }
+    }
+}
+
+/// Carries out a frame from a player. Boards are sent back through the game's updates, so only
+/// errors are returned.
+fn handle_frame(id: Uuid, team: Team, message: &Message) -> Result<(), ServerFrame> {
+    // pings and binary messages aren't commands
+    let Ok(text) = message.to_str() else {
+        return Ok(());
+    };
+    let frame = serde_json::from_str::<ClientFrame>(text).map_err(|err| ServerFrame::Error {
+        error: "invalid_frame",
+        message: err.to_string(),
+    })?;
+    match frame {
+        ClientFrame::Place { column } => place_in_turn(id, team, column)?,
+        ClientFrame::Reset => reset(id).map_err(PlaceError::from)?,
+    };
+    Ok(())
+}
+
+/// Like [`place`], but teams must take turns even if the game doesn't require it, so two
+/// connected players can't play over each other.
+fn place_in_turn(id: Uuid, team: Team, column: usize) -> Result<(), PlaceError> {
+    if column == 0 {
+        return Err(PlaceError::ColumnNotFound);
+    }
+
+    with_game(id, |game| {
+        game.check_turn(team)?;
+        game.place(team, column - 1)
+    })?
+}
+
+async fn send(ws: &mut WebSocket, frame: &ServerFrame) -> Result<(), salvo::Error> {
+    let text = serde_json::to_string(frame).map_err(salvo::Error::other)?;
+    ws.send(Message::text(text)).await
+}
+
+/// Plays in `seat` until either side closes the socket or the game expires.
+async fn play(
+    mut ws: WebSocket,
+    seat: Seat,
+    current: Board,
+    mut updates: broadcast::Receiver<Board>,
+) {
+    // players always take turns, so tell them whose turn it is
+    let mut frame = ServerFrame::Board {
+        board: BoardView::new(&current, true),
+    };
+    loop {
+        if send(&mut ws, &frame).await.is_err() {
+            return;
+        }
+        frame = loop {
+            tokio::select! {
+                message = ws.recv() => match message {
+                    Some(Ok(message)) if !message.is_close() => {
+                        if let Err(frame) = handle_frame(seat.id, seat.team, &message) {
+                            break frame;
+                        }
+                    }
+                    _ => return,
+                },
+                update = updates.recv() => match update {
+                    Ok(board) => break ServerFrame::Board {
+                        board: BoardView::new(&board, true),
+                    },
+                    // a newer board is on its way
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            }
+        };
+    }
+}
+
+fn history(id: Uuid) -> Result<Vec<Move>, GameError> {
+    with_game(id, |game| game.history.clone())
+}
+
+fn undo(id: Uuid) -> Result<Board, GameError> {
+    with_game(id, |game| {
+        game.undo()?;
+        Ok(game.board.clone())
+    })?
+}
+
+fn place(id: Uuid, team: Team, column: usize) -> Result<Board, PlaceError> {
+    if column == 0 {
+        return Err(PlaceError::ColumnNotFound);
+    }
+
+    with_game(id, |game| {
+        game.place(team, column - 1)?;
+        Ok(game.board.clone())
+    })?
+}
+
+/// Move chosen by the computer. Columns count from 1.
+#[derive(Debug, Serialize, ToSchema)]
+struct AiMove {
+    team: Team,
+    column: usize,
+    score: i32,
+    played: bool,
+}
+
+/// Searches a copy of the board off the async workers, so the game stays playable meanwhile. The
+/// move is only played if nobody changed the board during the search.
+async fn ai(id: Uuid, team: Team, depth: Option<u32>, play: bool) -> Result<AiMove, PlaceError> {
+    let board = board(id)?;
+    if board.state.is_game_over() {
+        return Err(PlaceError::GameOver(board));
+    }
+    let depth = depth
+        .unwrap_or(DEFAULT_AI_DEPTH)
+        .clamp(1, ai_depth_limit(board.columns()));
+
+    let searched = board.clone();
+    let (column, score) = tokio::task::spawn_blocking(move || searched.best_move(team, depth))
+        .await
+        .map_err(|_| GameError::InternalError)?
+        .ok_or_else(|| PlaceError::GameOver(board.clone()))?;
+    if play {
+        with_game(id, |game| {
+            if game.board.board != board.board {
+                return Err(PlaceError::BoardChanged);
+            }
+            game.place(team, column)
+        })??;
+    }
+    Ok(AiMove {
+        team,
+        column: column + 1,
+        score,
+        played: play,
+    })
+}
 
 #[endpoint(status_codes(200, 500))]
 async fn board_route() -> Result<Board, StatusCode> {
-    Ok(BOARD
-        .read()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .clone())
+    board(DEFAULT_GAME).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 #[endpoint(status_codes(200, 500))]
 async fn reset_route() -> Result<Board, StatusCode> {
-    let mut board = BOARD
-        .write()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    *board = Board::new();
-    Ok(board.clone())
+    reset(DEFAULT_GAME).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-#[derive(Debug, Clone, Copy, ToSchema, Deserialize)]
+#[endpoint(status_codes(200, 500))]
+async fn random_board_route() -> Result<Board, StatusCode> {
+    random_board(DEFAULT_GAME).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[endpoint(status_codes(200, 500))]
+async fn history_route() -> Result<Json<Vec<Move>>, StatusCode> {
+    Ok(Json(
+        history(DEFAULT_GAME).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    ))
+}
+
+#[endpoint]
+async fn undo_route() -> Result<Board, GameError> {
+    undo(DEFAULT_GAME)
+}
+
+/// Plays the game over a WebSocket as `team`. Players send `{"type": "place", "column": 1}` or
+/// `{"type": "reset"}` and receive `board` frames whenever the board changes, or `error` frames
+/// when a command fails. Each team can have one player, who must wait for the turn given by
+/// `next_player`. Joining as a team that already has a player fails with 409.
+#[endpoint]
+async fn ws_route(
+    team: QueryParam<Team, true>,
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    ws(DEFAULT_GAME, *team, req, res).await
+}
+
+/// Server-sent events with the board as JSON, sent whenever it changes.
+#[endpoint]
+async fn events_route(res: &mut Response) -> Result<(), GameError> {
+    events(DEFAULT_GAME, res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum Team {
     Milk,
     Cookie,
 }
 
+impl Team {
+    fn other(self) -> Self {
+        match self {
+            Self::Milk => Self::Cookie,
+            Self::Cookie => Self::Milk,
+        }
+    }
+}
+
+impl Display for Team {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Item::from(*self))
+    }
+}
+
 impl From<Team> for Item {
     fn from(value: Team) -> Self {
         match value {
@@ -265,22 +1029,32 @@ enum PlaceError {
     #[error("{0}")]
     GameOver(Board),
 
-    #[error("internal error")]
-    InternalError,
+    #[error("it is {0}'s turn")]
+    NotYourTurn(Team),
+
+    #[error("the board changed while the move was being chosen")]
+    BoardChanged,
+
+    #[error(transparent)]
+    GameError(#[from] GameError),
 }
 
 #[async_trait]
 impl Writer for PlaceError {
-    async fn write(self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+    async fn write(self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
         match self {
             Self::ColumnNotFound => {
                 res.status_code(StatusCode::BAD_REQUEST);
             }
-            Self::ColumnFull(_) | Self::GameOver(_) => {
+            Self::ColumnFull(board) | Self::GameOver(board) => {
                 res.status_code(StatusCode::SERVICE_UNAVAILABLE);
+                return board.write(req, depot, res).await;
             }
-            Self::InternalError => {
-                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            Self::NotYourTurn(_) | Self::BoardChanged => {
+                res.status_code(StatusCode::CONFLICT);
+            }
+            Self::GameError(err) => {
+                return err.write(req, depot, res).await;
             }
         }
         res.render(Text::Plain(self.to_string()));
@@ -288,7 +1062,8 @@ impl Writer for PlaceError {
 }
 
 impl EndpointOutRegister for PlaceError {
-    fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        GameError::register(components, operation);
         operation.responses.insert(
             StatusCode::BAD_REQUEST.as_str(),
             salvo::oapi::Response::new("bad request").add_content(
@@ -298,30 +1073,123 @@ impl EndpointOutRegister for PlaceError {
         );
         operation.responses.insert(
             StatusCode::SERVICE_UNAVAILABLE.as_str(),
-            salvo::oapi::Response::new("invalid state").add_content(
-                "text/plain",
-                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
-            ),
-        );
-        operation.responses.insert(
-            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
-            salvo::oapi::Response::new("internal error").add_content(
-                "text/plain",
-                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
-            ),
+            salvo::oapi::Response::new("invalid state")
+                .add_content(
+                    "text/plain",
+                    Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+                )
+                .add_content(
+                    "application/json",
+                    Content::new(BoardView::to_schema(components)),
+                ),
         );
     }
 }
 
 #[endpoint]
 async fn place_route(team: PathParam<Team>, column: PathParam<usize>) -> Result<Board, PlaceError> {
-    if *column == 0 {
-        return Err(PlaceError::ColumnNotFound);
+    place(DEFAULT_GAME, *team, *column)
+}
+
+/// Picks a move for `team`, searching `depth` moves ahead, and plays it if `play` is set. Wide
+/// boards are searched less deeply.
+#[endpoint]
+async fn ai_route(
+    team: PathParam<Team>,
+    depth: QueryParam<u32, false>,
+    play: QueryParam<bool, false>,
+) -> Result<RateLimited<Json<AiMove>>, PlaceError> {
+    Ok(RateLimited(Json(
+        ai(DEFAULT_GAME, *team, *depth, play.unwrap_or(false)).await?,
+    )))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NewGame {
+    id: Uuid,
+}
+
+/// Starts a new game, clearing out any that have expired. With `strict`, teams must take turns.
+/// Fails with 503 while too many games are live.
+#[endpoint(status_codes(201, 400, 500, 503))]
+async fn create_game_route(
+    strict: QueryParam<bool, false>,
+    size: BoardSize,
+    res: &mut Response,
+) -> Result<Json<NewGame>, GameError> {
+    let size = size.validate()?;
+    let mut games = GAMES.write().map_err(|_| GameError::InternalError)?;
+    games.retain(|id, game| !game.is_expired(*id));
+    // the default game doesn't count
+    if games.len() > MAX_GAMES {
+        return Err(GameError::TooManyGames);
     }
 
-    let mut board = BOARD.write().map_err(|_| PlaceError::InternalError)?;
-    board.place(*team, *column - 1)?;
-    Ok(board.clone())
+    let id = Uuid::new_v4();
+    games.insert(id, Game::new(RANDOM_SEED, strict.unwrap_or(false), size));
+    res.status_code(StatusCode::CREATED);
+    Ok(Json(NewGame { id }))
+}
+
+#[endpoint]
+async fn game_board_route(id: PathParam<Uuid>) -> Result<Board, GameError> {
+    board(*id)
+}
+
+#[endpoint]
+async fn game_reset_route(id: PathParam<Uuid>) -> Result<Board, GameError> {
+    reset(*id)
+}
+
+#[endpoint]
+async fn game_random_board_route(id: PathParam<Uuid>) -> Result<Board, GameError> {
+    random_board(*id)
+}
+
+#[endpoint]
+async fn game_history_route(id: PathParam<Uuid>) -> Result<Json<Vec<Move>>, GameError> {
+    Ok(Json(history(*id)?))
+}
+
+#[endpoint]
+async fn game_undo_route(id: PathParam<Uuid>) -> Result<Board, GameError> {
+    undo(*id)
+}
+
+#[endpoint]
+async fn game_events_route(id: PathParam<Uuid>, res: &mut Response) -> Result<(), GameError> {
+    events(*id, res)
+}
+
+#[endpoint]
+async fn game_ws_route(
+    id: PathParam<Uuid>,
+    team: QueryParam<Team, true>,
+    req: &mut Request,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    ws(*id, *team, req, res).await
+}
+
+#[endpoint]
+async fn game_ai_route(
+    id: PathParam<Uuid>,
+    team: PathParam<Team>,
+    depth: QueryParam<u32, false>,
+    play: QueryParam<bool, false>,
+) -> Result<RateLimited<Json<AiMove>>, PlaceError> {
+    Ok(RateLimited(Json(
+        ai(*id, *team, *depth, play.unwrap_or(false)).await?,
+    )))
+}
+
+#[endpoint]
+async fn game_place_route(
+    id: PathParam<Uuid>,
+    team: PathParam<Team>,
+    column: PathParam<usize>,
+) -> Result<Board, PlaceError> {
+    place(*id, *team, *column)
 }
 
 pub fn get_router() -> Router {
@@ -329,4 +1197,122 @@ pub fn get_router() -> Router {
         .push(Router::with_path("/12/board").get(board_route))
         .push(Router::with_path("/12/reset").post(reset_route))
         .push(Router::with_path("/12/place/<team>/<column>").post(place_route))
+        .push(Router::with_path("/12/random-board").get(random_board_route))
+        .push(Router::with_path("/12/history").get(history_route))
+        .push(Router::with_path("/12/undo").post(undo_route))
+        .push(Router::with_path("/12/events").get(events_route))
+        .push(Router::with_path("/12/ws").get(ws_route))
+        .push(
+            Router::with_path("/12/ai/<team>")
+                .hoop(AI_LIMITER.clone())
+                .post(ai_route),
+        )
+        .push(Router::with_path("/12/games").post(create_game_route))
+        .push(Router::with_path("/12/games/<id>/board").get(game_board_route))
+        .push(Router::with_path("/12/games/<id>/reset").post(game_reset_route))
+        .push(Router::with_path("/12/games/<id>/place/<team>/<column>").post(game_place_route))
+        .push(Router::with_path("/12/games/<id>/random-board").get(game_random_board_route))
+        .push(Router::with_path("/12/games/<id>/history").get(game_history_route))
+        .push(Router::with_path("/12/games/<id>/undo").post(game_undo_route))
+        .push(Router::with_path("/12/games/<id>/events").get(game_events_route))
+        .push(Router::with_path("/12/games/<id>/ws").get(game_ws_route))
+        .push(
+            Router::with_path("/12/games/<id>/ai/<team>")
+                .hoop(AI_LIMITER.clone())
+                .post(game_ai_route),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A board from its rows, top to bottom, with `c` for cookie, `m` for milk and `.` for empty.
+    fn board(rows: &[&str], win_length: usize) -> Board {
+        let mut board = Board::new(BoardSize {
+            rows: rows.len(),
+            columns: rows[0].len(),
+            win_length,
+        });
+        for (row, cells) in rows.iter().enumerate() {
+            for (column, cell) in cells.chars().enumerate() {
+                board.board[row][column] = match cell {
+                    'c' => Item::Cookie,
+                    'm' => Item::Milk,
+                    _ => Item::Empty,
+                };
+            }
+        }
+        board.check_win();
+        board
+    }
+
+    #[test]
+    fn default_board_wins_as_before() {
+        let empty = board(&["....", "....", "....", "...."], 4);
+        assert!(matches!(empty.state, GameState::NotEnded));
+        let three = board(&["....", "....", "....", "ccc."], 4);
+        assert!(matches!(three.state, GameState::NotEnded));
+        let row = board(&["....", "....", "....", "cccc"], 4);
+        assert!(matches!(row.state, GameState::CookieWon));
+        let column = board(&["m...", "m...", "m...", "m..."], 4);
+        assert!(matches!(column.state, GameState::MilkWon));
+        let diagonal = board(&["c...", ".c..", "..c.", "...c"], 4);
+        assert!(matches!(diagonal.state, GameState::CookieWon));
+        let anti_diagonal = board(&["...m", "..m.", ".m..", "m..."], 4);
+        assert!(matches!(anti_diagonal.state, GameState::MilkWon));
+        let full = board(&["cmcm", "cmcm", "mcmc", "mcmc"], 4);
+        assert!(matches!(full.state, GameState::NoWinner));
+    }
+
+    #[test]
+    fn default_random_board_is_unchanged() {
+        let mut rng = StdRng::seed_from_u64(RANDOM_SEED);
+        let board = Board::random(BoardSize::default(), &mut rng);
+        // milk has a row too, but the top row is found first
+        assert_eq!(
+            board.to_string(),
+            "⬜🍪🍪🍪🍪⬜\n⬜🥛🍪🍪🥛⬜\n⬜🥛🥛🥛🥛⬜\n⬜🍪🥛🍪🥛⬜\n⬜⬜⬜⬜⬜⬜\n🍪 wins!\n"
+        );
+    }
+
+    #[test]
+    fn wide_board_finds_off_centre_anti_diagonal() {
+        let won = board(
+            &[
+                ".......", ".......", "......m", ".....mc", "....mcc", "...mccm",
+            ],
+            4,
+        );
+        assert!(matches!(won.state, GameState::MilkWon));
+
+        let three = board(
+            &[
+                ".......", ".......", ".......", ".....mc", "....mcc", "...mccm",
+            ],
+            4,
+        );
+        assert!(matches!(three.state, GameState::NotEnded));
+
+        // lines don't wrap onto the next row
+        let wrapped = board(
+            &[
+                ".......", ".......", ".......", ".......", "....ccc", "c......",
+            ],
+            4,
+        );
+        assert!(matches!(wrapped.state, GameState::NotEnded));
+    }
+
+    #[test]
+    fn win_length_longer_than_a_side() {
+        let column = board(&["c.....", "c.....", "c....."], 5);
+        assert!(matches!(column.state, GameState::NotEnded));
+        let short = board(&["......", "......", "mmmm.."], 5);
+        assert!(matches!(short.state, GameState::NotEnded));
+        let row = board(&["......", "......", "mmmmm."], 5);
+        assert!(matches!(row.state, GameState::MilkWon));
+        let full = board(&["ccccmm", "mmmmcc", "ccccmm"], 5);
+        assert!(matches!(full.state, GameState::NoWinner));
+    }
 }
@@ -4,7 +4,7 @@ use rsa::{
     pkcs8::LineEnding,
     RsaPrivateKey, RsaPublicKey,
 };
-use std::{collections::HashSet, sync::LazyLock};
+use std::{collections::HashSet, sync::LazyLock, time::Duration};
 
 use salvo::{
     http::{cookie::Cookie, ParseError},
@@ -16,6 +16,12 @@ use salvo::{
     Extractible,
 };
 
+use crate::rate_limit::{LimitConfig, RateLimited, RateLimiter};
+
+/// `WRAP_BUCKET_CAPACITY` and `WRAP_REFILL_INTERVAL_MS`. Signing with RSA is expensive.
+static WRAP_LIMIT: LazyLock<LimitConfig> =
+    LazyLock::new(|| LimitConfig::from_env("WRAP", 20, Duration::from_millis(200)));
+
 static PRIVATE_KEY: LazyLock<RsaPrivateKey> = LazyLock::new(|| {
     let mut rng = rand::thread_rng();
     let bits = 2048;
@@ -114,11 +120,14 @@ impl EndpointOutRegister for WrapError {
 const ALGORITHM: Algorithm = Algorithm::RS256;
 
 #[endpoint]
-async fn wrap_route(data: WrapInput, res: &mut Response) -> Result<&'static str, WrapError> {
+async fn wrap_route(
+    data: WrapInput,
+    res: &mut Response,
+) -> Result<RateLimited<&'static str>, WrapError> {
     let jsoned: serde_json::Value = serde_json::from_slice(&data.text)?;
     let encoded = encode(&Header::new(ALGORITHM), &jsoned, &ENCODING_KEY)?;
     res.add_cookie(Cookie::new("gift", encoded));
-    Ok("")
+    Ok(RateLimited(""))
 }
 
 #[endpoint]
@@ -133,6 +142,14 @@ async fn unwrap_route(gift: CookieParam<String>) -> Result<String, WrapError> {
 
 pub fn get_router() -> Router {
     Router::new()
-        .push(Router::with_path("/16/wrap").post(wrap_route))
+        .push(
+            Router::with_path("/16/wrap")
+                .hoop(RateLimiter::new(
+                    "gift-wrap",
+                    WRAP_LIMIT.capacity,
+                    WRAP_LIMIT.refill_interval,
+                ))
+                .post(wrap_route),
+        )
         .push(Router::with_path("/16/unwrap").get(unwrap_route))
 }
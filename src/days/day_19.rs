@@ -1,3 +1,5 @@
+use std::{sync::LazyLock, time::Duration};
+
 use chrono::{DateTime, Local};
 use salvo::{
     oapi::{
@@ -9,7 +11,14 @@ use salvo::{
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::DB_POOL;
+use crate::{
+    db::DB_POOL,
+    rate_limit::{LimitConfig, RateLimited, RateLimiter},
+};
+
+/// `DRAFT_BUCKET_CAPACITY` and `DRAFT_REFILL_INTERVAL_MS`. Every draft is a database write.
+static DRAFT_LIMIT: LazyLock<LimitConfig> =
+    LazyLock::new(|| LimitConfig::from_env("DRAFT", 20, Duration::from_millis(200)));
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 struct Quote {
@@ -115,11 +124,11 @@ async fn undo_route(
     Ok(Json(quote))
 }
 
-#[endpoint(status_codes(201, 404, 500))]
+#[endpoint(status_codes(201, 404, 429, 500))]
 async fn draft_route(
     input: JsonBody<QuoteInput>,
     res: &mut Response,
-) -> Result<Json<Quote>, QuotesError> {
+) -> Result<RateLimited<Json<Quote>>, QuotesError> {
     let id = Uuid::new_v4();
     sqlx::query("insert into quotes (id, author, quote) values ($1, $2, $3) returning id")
         .bind(id)
@@ -133,7 +142,7 @@ async fn draft_route(
         .await?
         .ok_or(QuotesError::NotFound)?;
     res.status_code(StatusCode::CREATED);
-    Ok(Json(quote))
+    Ok(RateLimited(Json(quote)))
 }
 
 pub fn get_router() -> Router {
@@ -142,5 +151,13 @@ pub fn get_router() -> Router {
         .push(Router::with_path("/19/cite/<id>").get(cite_route))
         .push(Router::with_path("/19/remove/<id>").delete(remove_route))
         .push(Router::with_path("/19/undo/<id>").put(undo_route))
-        .push(Router::with_path("/19/draft").post(draft_route))
+        .push(
+            Router::with_path("/19/draft")
+                .hoop(RateLimiter::new(
+                    "quotes",
+                    DRAFT_LIMIT.capacity,
+                    DRAFT_LIMIT.refill_interval,
+                ))
+                .post(draft_route),
+        )
 }
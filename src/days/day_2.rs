@@ -1,6 +1,14 @@
-use std::net::Ipv4Addr;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr};
 
-use salvo::{oapi::extract::QueryParam, prelude::*};
+use ipnet::{IpNet, PrefixLenError};
+use salvo::{
+    oapi::{
+        extract::{JsonBody, QueryParam},
+        BasicType, Content, Object, Schema,
+    },
+    prelude::*,
+};
+use serde::Serialize;
 
 #[endpoint]
 async fn dest(from: QueryParam<Ipv4Addr, true>, key: QueryParam<Ipv4Addr, true>) -> String {
@@ -28,8 +36,147 @@ async fn key_(from: QueryParam<Ipv4Addr, true>, to: QueryParam<Ipv4Addr, true>)
     result.to_string()
 }
 
+fn xor_v6(left: Ipv6Addr, right: Ipv6Addr) -> Ipv6Addr {
+    let left = left.segments();
+    let right = right.segments();
+    Ipv6Addr::from(std::array::from_fn::<u16, 8, _>(|i| left[i] ^ right[i]))
+}
+
+#[endpoint]
+async fn dest_v6(from: QueryParam<Ipv6Addr, true>, key: QueryParam<Ipv6Addr, true>) -> String {
+    xor_v6(*from, *key).to_string()
+}
+
+#[endpoint]
+async fn key_v6(from: QueryParam<Ipv6Addr, true>, to: QueryParam<Ipv6Addr, true>) -> String {
+    // xor is its own inverse
+    xor_v6(*to, *from).to_string()
+}
+
+/// Largest number of subnets `/2/subnet/split` will list in one response.
+const MAX_SPLIT_SUBNETS: u32 = 1024;
+
+#[derive(Debug, thiserror::Error)]
+enum SubnetError {
+    #[error("invalid cidr: {0}")]
+    InvalidCidr(#[from] ipnet::AddrParseError),
+
+    #[error("invalid address: {0}")]
+    InvalidAddress(#[from] AddrParseError),
+
+    #[error("invalid prefix length")]
+    InvalidPrefix(#[from] PrefixLenError),
+
+    #[error("split would produce more than {} subnets", MAX_SPLIT_SUBNETS)]
+    TooManySubnets,
+}
+
+impl Scribe for SubnetError {
+    fn render(self, res: &mut Response) {
+        res.status_code(StatusCode::BAD_REQUEST);
+        res.render(Text::Plain(self.to_string()));
+    }
+}
+
+impl EndpointOutRegister for SubnetError {
+    fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        operation.responses.insert(
+            StatusCode::BAD_REQUEST.as_str(),
+            salvo::oapi::Response::new("bad request").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SubnetInfo {
+    network: String,
+    /// `null` for IPv6, which has no broadcast address
+    broadcast: Option<String>,
+    netmask: String,
+    hostmask: String,
+    prefix_len: u8,
+    /// Number of usable host addresses, as a string since IPv6 counts overflow JSON numbers.
+    hosts: String,
+}
+
+/// Number of usable hosts in `net`. Saturates at `u128::MAX` for `::/0`.
+fn host_count(net: &IpNet) -> u128 {
+    let host_bits = u32::from(net.max_prefix_len() - net.prefix_len());
+    let total = 1u128.checked_shl(host_bits).unwrap_or(u128::MAX);
+    match net {
+        // the network and broadcast addresses are not usable, except on point-to-point links
+        IpNet::V4(_) if host_bits > 1 => total - 2,
+        _ => total,
+    }
+}
+
+#[endpoint]
+async fn subnet_route(cidr: QueryParam<String, true>) -> Result<Json<SubnetInfo>, SubnetError> {
+    let net: IpNet = cidr.parse()?;
+    Ok(Json(SubnetInfo {
+        network: net.network().to_string(),
+        broadcast: match net {
+            IpNet::V4(net) => Some(net.broadcast().to_string()),
+            IpNet::V6(_) => None,
+        },
+        netmask: net.netmask().to_string(),
+        hostmask: net.hostmask().to_string(),
+        prefix_len: net.prefix_len(),
+        hosts: host_count(&net).to_string(),
+    }))
+}
+
+#[endpoint]
+async fn subnet_contains_route(
+    cidr: QueryParam<String, true>,
+    addr: QueryParam<String, true>,
+) -> Result<Json<bool>, SubnetError> {
+    let net: IpNet = cidr.parse()?;
+    let addr: IpAddr = addr.parse()?;
+    Ok(Json(net.contains(&addr)))
+}
+
+#[endpoint]
+async fn subnet_split_route(
+    cidr: QueryParam<String, true>,
+    prefix: QueryParam<u8, true>,
+) -> Result<Json<Vec<String>>, SubnetError> {
+    let net: IpNet = cidr.parse()?;
+    let subnets = net.subnets(*prefix)?;
+    let count = 1u32.checked_shl(u32::from(*prefix - net.prefix_len()));
+    if count.is_none_or(|count| count > MAX_SPLIT_SUBNETS) {
+        return Err(SubnetError::TooManySubnets);
+    }
+    Ok(Json(subnets.map(|subnet| subnet.to_string()).collect()))
+}
+
+#[endpoint]
+async fn subnet_summarize_route(
+    cidrs: JsonBody<Vec<String>>,
+) -> Result<Json<Vec<String>>, SubnetError> {
+    let nets = cidrs
+        .iter()
+        .map(|cidr| cidr.parse())
+        .collect::<Result<Vec<IpNet>, _>>()?;
+    Ok(Json(
+        IpNet::aggregate(&nets)
+            .into_iter()
+            .map(|net| net.to_string())
+            .collect(),
+    ))
+}
+
 pub fn get_router() -> Router {
     Router::new()
         .push(Router::with_path("/2/dest").get(dest))
         .push(Router::with_path("/2/key").get(key_))
+        .push(Router::with_path("/2/v6/dest").get(dest_v6))
+        .push(Router::with_path("/2/v6/key").get(key_v6))
+        .push(Router::with_path("/2/subnet").get(subnet_route))
+        .push(Router::with_path("/2/subnet/contains").get(subnet_contains_route))
+        .push(Router::with_path("/2/subnet/split").get(subnet_split_route))
+        .push(Router::with_path("/2/subnet/summarize").post(subnet_summarize_route))
 }
@@ -1,9 +1,15 @@
 use std::{fmt::Display, str::FromStr};
 
 use salvo::{
-    oapi::{extract::PathParam, BasicType, Content, Object, Schema},
+    http::ParseError,
+    oapi::{
+        extract::PathParam, BasicType, Content, KnownFormat, Object, RequestBody, Schema,
+        SchemaFormat,
+    },
     prelude::*,
+    Extractible,
 };
+use serde::Deserialize;
 
 #[derive(Debug)]
 struct Html {
@@ -188,9 +194,175 @@ async fn ornament_route(state: PathParam<String>, n: PathParam<String>) -> Resul
     )))
 }
 
+#[derive(Debug, thiserror::Error)]
+enum LockfileError {
+    #[error("could not read form: {0}")]
+    FormError(#[from] ParseError),
+
+    #[error("lockfile not provided")]
+    MissingFile,
+
+    #[error("could not read lockfile: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("invalid lockfile: {0}")]
+    InvalidLockfile(#[from] toml::de::Error),
+
+    #[error("invalid checksum")]
+    InvalidChecksum,
+}
+
+impl Scribe for LockfileError {
+    fn render(self, res: &mut Response) {
+        match self {
+            Self::FormError(_) | Self::MissingFile | Self::InvalidLockfile(_) => {
+                res.status_code(StatusCode::BAD_REQUEST)
+            }
+            Self::IoError(_) => res.status_code(StatusCode::INTERNAL_SERVER_ERROR),
+            Self::InvalidChecksum => res.status_code(StatusCode::UNPROCESSABLE_ENTITY),
+        };
+        res.render(Text::Plain(self.to_string()));
+    }
+}
+
+impl EndpointOutRegister for LockfileError {
+    fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        operation.responses.insert(
+            StatusCode::BAD_REQUEST.as_str(),
+            salvo::oapi::Response::new("lockfile missing or not valid TOML").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::UNPROCESSABLE_ENTITY.as_str(),
+            salvo::oapi::Response::new("invalid checksum").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
+            salvo::oapi::Response::new("internal error").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+    }
+}
+
+#[derive(Debug)]
+struct LockfileInput {
+    text: String,
+}
+
+impl<'ex> Extractible<'ex> for LockfileInput {
+    fn metadata() -> &'ex salvo::extract::Metadata {
+        static METADATA: salvo::extract::Metadata = salvo::extract::Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + std::fmt::Debug + 'static> {
+        req.form_data().await?;
+        let file = req
+            .file("lockfile")
+            .await
+            .ok_or(LockfileError::MissingFile)?;
+        Ok::<Self, LockfileError>(Self {
+            text: tokio::fs::read_to_string(file.path()).await?,
+        })
+    }
+}
+
+impl EndpointArgRegister for LockfileInput {
+    fn register(
+        _components: &mut salvo::oapi::Components,
+        operation: &mut salvo::oapi::Operation,
+        _arg: &str,
+    ) {
+        operation.request_body = Some(
+            RequestBody::new()
+                .description("Cargo.lock upload")
+                .add_content(
+                    "multipart/form-data",
+                    Content::new(Schema::Object(
+                        Object::new()
+                            .property(
+                                "lockfile",
+                                Object::new()
+                                    .schema_type(BasicType::String)
+                                    .format(SchemaFormat::KnownFormat(KnownFormat::Binary)),
+                            )
+                            .required("lockfile"),
+                    )),
+                ),
+        );
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedPackage {
+    checksum: Option<String>,
+}
+
+/// A sprinkle on the lockfile canvas, derived from the first 10 hex digits of a checksum.
+#[derive(Debug)]
+struct Sprinkle<'a> {
+    color: &'a str,
+    top: u8,
+    left: u8,
+}
+
+impl<'a> TryFrom<&'a str> for Sprinkle<'a> {
+    type Error = LockfileError;
+
+    fn try_from(checksum: &'a str) -> Result<Self, Self::Error> {
+        let digits = checksum.get(0..10).ok_or(LockfileError::InvalidChecksum)?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(LockfileError::InvalidChecksum);
+        }
+        // all ascii hex digits, so these can't fail
+        Ok(Self {
+            color: &digits[0..6],
+            top: u8::from_str_radix(&digits[6..8], 16).unwrap(),
+            left: u8::from_str_radix(&digits[8..10], 16).unwrap(),
+        })
+    }
+}
+
+impl Display for Sprinkle<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            r#"<div style="background-color:#{};top:{}px;left:{}px;"></div>"#,
+            self.color, self.top, self.left
+        )
+    }
+}
+
+#[endpoint]
+async fn lockfile_route(data: LockfileInput) -> Result<Html, LockfileError> {
+    let lockfile: Lockfile = toml::from_str(&data.text)?;
+    let sprinkles = lockfile
+        .package
+        .iter()
+        .filter_map(|package| package.checksum.as_deref())
+        .map(|checksum| Sprinkle::try_from(checksum).map(|sprinkle| sprinkle.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Html::new(sprinkles.join("\n")))
+}
+
 pub fn get_router() -> Router {
     Router::new()
         .push(Router::with_path("/23/star").get(star_route))
         .push(Router::with_path("/23/present/<color>").get(present_color_route))
         .push(Router::with_path("/23/ornament/<state>/<n>").get(ornament_route))
+        .push(Router::with_path("/23/lockfile").post(lockfile_route))
 }
@@ -1,23 +1,420 @@
-use cargo_toml::Manifest;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io,
+    path::Path,
+};
+
+use cargo_toml::{AbstractFilesystem, Dependency, Manifest, Package};
 use salvo::{
-    http::ParseError,
-    oapi::{BasicType, Content, Object, RequestBody, Schema},
+    http::{form::FilePart, header::CONTENT_TYPE, HeaderValue, ParseError},
+    oapi::{Array, BasicType, Content, KnownFormat, Object, RequestBody, Schema, SchemaFormat},
     prelude::*,
     Extractible,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct Order {
     pub item: String,
     pub quantity: usize,
+    /// Workspace member the order came from, for workspace uploads
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RejectedOrder {
+    /// Position of the order in `package.metadata.orders`
+    pub index: usize,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum OrderRule {
+    NonZeroQuantity,
+    MaxQuantity,
+    Catalog,
+}
+
+impl std::fmt::Display for OrderRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NonZeroQuantity => "non_zero_quantity",
+            Self::MaxQuantity => "max_quantity",
+            Self::Catalog => "catalog",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct OrderViolation {
+    pub order: Order,
+    pub rule: OrderRule,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ItemTotal {
+    pub item: String,
+    pub quantity: usize,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct OrderReport {
+    pub orders: Vec<Order>,
+    pub rejected: Vec<RejectedOrder>,
+    /// Orders that parsed but broke a rule, only checked when aggregating
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<OrderViolation>,
+    /// Quantity per item over all accepted orders, only when aggregating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totals: Option<Vec<ItemTotal>>,
+}
+
+/// Query parameters of `/5/manifest` controlling aggregation and which orders are accepted.
+#[derive(Debug, Deserialize, ToParameters)]
+#[salvo(parameters(default_parameter_in = Query))]
+struct OrderRules {
+    /// Sum quantities per item. Orders are also checked against the rules below, which are
+    /// checked on their own whenever any of them is given.
+    #[serde(default)]
+    aggregate: bool,
+    /// Largest quantity a single order may have
+    max_quantity: Option<usize>,
+    /// Comma-separated items that may be ordered. Any item is allowed if not set.
+    catalog: Option<String>,
+    /// Accept orders with a quantity of 0
+    #[serde(default)]
+    allow_zero: bool,
+}
+
+impl OrderRules {
+    fn check(&self, order: &Order) -> Result<(), OrderRule> {
+        if !self.allow_zero && order.quantity == 0 {
+            return Err(OrderRule::NonZeroQuantity);
+        }
+        if self.max_quantity.is_some_and(|max| order.quantity > max) {
+            return Err(OrderRule::MaxQuantity);
+        }
+        if let Some(catalog) = &self.catalog {
+            if !catalog.split(',').any(|item| item.trim() == order.item) {
+                return Err(OrderRule::Catalog);
+            }
+        }
+        Ok(())
+    }
+
+    fn any_rules(&self) -> bool {
+        self.max_quantity.is_some() || self.catalog.is_some() || self.allow_zero
+    }
+
+    /// Checks orders if asked to, then totals them up if aggregating.
+    fn apply(&self, report: &mut OrderReport) {
+        if self.aggregate || self.any_rules() {
+            self.reject_violations(report);
+        }
+        if self.aggregate {
+            self.total(report);
+        }
+    }
+
+    /// Moves orders breaking a rule into `report.violations`.
+    fn reject_violations(&self, report: &mut OrderReport) {
+        for order in std::mem::take(&mut report.orders) {
+            match self.check(&order) {
+                Ok(()) => report.orders.push(order),
+                Err(rule) => report.violations.push(OrderViolation { order, rule }),
+            }
+        }
+    }
+
+    fn total(&self, report: &mut OrderReport) {
+        let mut totals = BTreeMap::<String, usize>::new();
+        for order in &report.orders {
+            let total = totals.entry(order.item.clone()).or_default();
+            *total = total.saturating_add(order.quantity);
+        }
+        report.totals = Some(
+            totals
+                .into_iter()
+                .map(|(item, quantity)| ItemTotal { item, quantity })
+                .collect(),
+        );
+    }
+}
+
+#[derive(Debug)]
+enum ManifestOutput {
+    Text(String),
+    Json(OrderReport),
+}
+
+#[async_trait]
+impl Writer for ManifestOutput {
+    async fn write(self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
+        match self {
+            Self::Text(s) => {
+                res.render(Text::Plain(s));
+            }
+            Self::Json(report) => {
+                res.render(Json(report));
+            }
+        }
+    }
+}
+
+impl EndpointOutRegister for ManifestOutput {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        operation.responses.insert(
+            StatusCode::OK.as_str(),
+            salvo::oapi::Response::new("Orders found")
+                .add_content(
+                    "text/plain",
+                    Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+                )
+                .add_content(
+                    "application/json",
+                    Content::new(OrderReport::to_schema(components)),
+                ),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ManifestFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ManifestFormat {
+    const MEDIA_TYPES: [(&'static str, Self); 3] = [
+        ("application/toml", Self::Toml),
+        ("application/yaml", Self::Yaml),
+        ("application/json", Self::Json),
+    ];
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        Self::MEDIA_TYPES
+            .iter()
+            .find(|(name, _)| *name == media_type)
+            .map(|(_, format)| *format)
+    }
+
+    fn parse(self, text: &[u8]) -> Result<Manifest, Day5Error> {
+        let diagnostic = match self {
+            Self::Toml => {
+                Manifest::from_slice(text).map_err(|err| ManifestDiagnostic::from_toml(&err, text))
+            }
+            Self::Yaml => {
+                serde_yaml::from_slice(text).map_err(|err| ManifestDiagnostic::from_yaml(&err))
+            }
+            Self::Json => {
+                serde_json::from_slice(text).map_err(|err| ManifestDiagnostic::from_json(&err))
+            }
+        };
+        diagnostic.map_err(Day5Error::ParseError)
+    }
+}
+
+/// Where in the submitted manifest a parse error occurred. Lines and columns are 1-based.
+#[derive(Debug, Serialize, ToSchema)]
+struct SourceSpan {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offsets into the body, when the parser reports them
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+}
+
+#[derive(Debug)]
+struct ManifestDiagnostic {
+    detail: String,
+    span: Option<SourceSpan>,
+    key: Option<String>,
+    /// Uploaded file the span refers to, for workspace uploads
+    file: Option<String>,
+}
+
+impl ManifestDiagnostic {
+    fn from_toml(err: &cargo_toml::Error, text: &[u8]) -> Self {
+        let cargo_toml::Error::Parse(err) = err else {
+            return Self {
+                detail: err.to_string(),
+                span: None,
+                key: None,
+                file: None,
+            };
+        };
+        // toml spans are byte offsets into valid utf-8, otherwise parsing would not have started
+        let text = std::str::from_utf8(text).unwrap_or_default();
+        let Some(range) = err
+            .span()
+            .filter(|range| text.is_char_boundary(range.start))
+        else {
+            return Self {
+                detail: err.message().to_owned(),
+                span: None,
+                key: None,
+                file: None,
+            };
+        };
+
+        let before = &text[..range.start];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        let line_end = text[range.start..]
+            .find('\n')
+            .map_or(text.len(), |index| range.start + index);
+        Self {
+            detail: err.message().to_owned(),
+            span: Some(SourceSpan {
+                line: before.matches('\n').count() + 1,
+                column: before[line_start..].chars().count() + 1,
+                start: Some(range.start),
+                end: Some(range.end),
+            }),
+            key: toml_key_path(&text[..line_start], &text[line_start..line_end]),
+            file: None,
+        }
+    }
+
+    fn from_yaml(err: &serde_yaml::Error) -> Self {
+        Self {
+            detail: err.to_string(),
+            span: err.location().map(|location| SourceSpan {
+                line: location.line(),
+                column: location.column(),
+                start: Some(location.index()),
+                end: None,
+            }),
+            key: None,
+            file: None,
+        }
+    }
+
+    fn from_json(err: &serde_json::Error) -> Self {
+        Self {
+            detail: err.to_string(),
+            span: (err.line() > 0).then(|| SourceSpan {
+                line: err.line(),
+                column: err.column(),
+                start: None,
+                end: None,
+            }),
+            key: None,
+            file: None,
+        }
+    }
+}
+
+/// Whether `key` is a bare, quoted or dotted TOML key.
+fn is_toml_key(key: &str) -> bool {
+    let mut rest = key.trim();
+    loop {
+        rest = if let Some(quoted) = rest.strip_prefix('"') {
+            match quoted.find('"') {
+                Some(end) => &quoted[end + 1..],
+                None => return false,
+            }
+        } else if let Some(quoted) = rest.strip_prefix('\'') {
+            match quoted.find('\'') {
+                Some(end) => &quoted[end + 1..],
+                None => return false,
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return false;
+            }
+            &rest[end..]
+        };
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return true;
+        }
+        match rest.strip_prefix('.') {
+            Some(next) => rest = next.trim_start(),
+            None => return false,
+        }
+    }
+}
+
+/// Best-effort dotted path of the key on `line`, qualified by the nearest table header in
+/// `preceding`. A table header line is its own key. Lines that are not a header or a `key =`
+/// assignment, such as those inside inline tables or multi-line arrays, have no key.
+fn toml_key_path(preceding: &str, line: &str) -> Option<String> {
+    fn header(line: &str) -> Option<&str> {
+        let line = line.trim();
+        let (inner, rest) = match line.strip_prefix("[[") {
+            Some(line) => line.split_once("]]")?,
+            None => line.strip_prefix('[')?.split_once(']')?,
+        };
+        let rest = rest.trim_start();
+        (is_toml_key(inner) && (rest.is_empty() || rest.starts_with('#'))).then(|| inner.trim())
+    }
+
+    if let Some(header) = header(line) {
+        return Some(header.to_owned());
+    }
+    let key = line
+        .split_once('=')
+        .map(|(key, _)| key.trim())
+        .filter(|key| is_toml_key(key))?;
+    match preceding.lines().rev().find_map(header) {
+        Some(table) => Some(format!("{table}.{key}")),
+        None => Some(key.to_owned()),
+    }
+}
+
+/// An RFC 9457 problem document describing why a manifest was rejected.
+#[derive(Debug, Serialize, ToSchema)]
+struct ManifestProblem {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub span: Option<SourceSpan>,
+    /// Dotted path of the offending key, when it can be determined
+    pub key: Option<String>,
+    /// Uploaded file the span refers to, for workspace uploads
+    pub file: Option<String>,
+}
+
+#[derive(Debug)]
+enum ManifestInput {
+    Single {
+        format: ManifestFormat,
+        text: Vec<u8>,
+    },
+    /// A multipart upload of a `workspace` root manifest and any number of `member` manifests,
+    /// all as TOML.
+    Workspace {
+        root: UploadedManifest,
+        members: Vec<UploadedManifest>,
+    },
 }
 
 #[derive(Debug)]
-struct ManifestInput {
+struct UploadedManifest {
+    /// File name given by the client, or the form field and index if there was none
+    name: String,
     text: Vec<u8>,
 }
 
+impl UploadedManifest {
+    async fn read(file: &FilePart, fallback_name: String) -> io::Result<Self> {
+        Ok(Self {
+            name: file.name().map_or(fallback_name, ToOwned::to_owned),
+            text: tokio::fs::read(file.path()).await?,
+        })
+    }
+}
+
 impl<'ex> Extractible<'ex> for ManifestInput {
     fn metadata() -> &'ex salvo::extract::Metadata {
         static METADATA: salvo::extract::Metadata = salvo::extract::Metadata::new("");
@@ -27,7 +424,41 @@ impl<'ex> Extractible<'ex> for ManifestInput {
     async fn extract(
         req: &'ex mut Request,
     ) -> Result<Self, impl Writer + Send + std::fmt::Debug + 'static> {
-        Ok::<Self, ParseError>(Self {
+        Self::read(req).await
+    }
+}
+
+impl ManifestInput {
+    async fn read(req: &mut Request) -> Result<Self, Day5Error> {
+        let content_type = req.content_type();
+        let media_type = content_type.as_ref().map(|mime| mime.essence_str());
+        if media_type == Some("multipart/form-data") {
+            let form = req.form_data().await?;
+            let root = form
+                .files
+                .get("workspace")
+                .ok_or(Day5Error::MissingWorkspace)?;
+            let members = form
+                .files
+                .get_vec("member")
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let root = UploadedManifest::read(root, "workspace".to_owned()).await?;
+            let mut uploaded = Vec::with_capacity(members.len());
+            for (index, member) in members.iter().enumerate() {
+                uploaded.push(UploadedManifest::read(member, format!("member[{index}]")).await?);
+            }
+            return Ok(Self::Workspace {
+                root,
+                members: uploaded,
+            });
+        }
+
+        let format = media_type
+            .and_then(ManifestFormat::from_media_type)
+            .ok_or(Day5Error::UnsupportedMediaType)?;
+        Ok(Self::Single {
+            format,
             text: req.payload().await?.into_iter().cloned().collect(),
         })
     }
@@ -39,13 +470,32 @@ impl EndpointArgRegister for ManifestInput {
         operation: &mut salvo::oapi::Operation,
         _arg: &str,
     ) {
-        operation.request_body = Some(
-            RequestBody::new()
-                .description("Manifest as TOML")
-                .add_content(
-                    "text/plain",
+        let request_body = RequestBody::new()
+            .description("Manifest as TOML, YAML or JSON, or a multipart workspace upload");
+        let request_body = ManifestFormat::MEDIA_TYPES.iter().fold(
+            request_body,
+            |request_body, (media_type, _)| {
+                request_body.add_content(
+                    *media_type,
                     Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
-                ),
+                )
+            },
+        );
+        let file = || {
+            Object::new()
+                .schema_type(BasicType::String)
+                .format(SchemaFormat::KnownFormat(KnownFormat::Binary))
+        };
+        operation.request_body = Some(
+            request_body.add_content(
+                "multipart/form-data",
+                Content::new(Schema::Object(
+                    Object::new()
+                        .property("workspace", file())
+                        .property("member", Array::new().items(file()))
+                        .required("workspace"),
+                )),
+            ),
         );
     }
 }
@@ -53,7 +503,23 @@ impl EndpointArgRegister for ManifestInput {
 #[derive(Debug, thiserror::Error)]
 enum Day5Error {
     #[error("Invalid manifest")]
-    ParseError(#[from] cargo_toml::Error),
+    ParseError(ManifestDiagnostic),
+
+    /// Along with the uploaded file lacking it, for workspace uploads
+    #[error("Manifest has no [package] table")]
+    MissingPackage(Option<String>),
+
+    #[error("Workspace root manifest not provided or has no [workspace] table")]
+    MissingWorkspace,
+
+    #[error("could not read upload: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("could not read body: {0}")]
+    PayloadError(#[from] ParseError),
+
+    #[error("Unsupported media type")]
+    UnsupportedMediaType,
 
     #[error("Magic keyword not provided")]
     NoMagicKeyword,
@@ -62,18 +528,73 @@ enum Day5Error {
     NoOrders,
 }
 
+impl Day5Error {
+    /// Points a manifest error at the uploaded file it came from.
+    fn in_file(self, name: &str) -> Self {
+        match self {
+            Self::ParseError(diagnostic) => Self::ParseError(ManifestDiagnostic {
+                file: Some(name.to_owned()),
+                ..diagnostic
+            }),
+            Self::MissingPackage(_) => Self::MissingPackage(Some(name.to_owned())),
+            err => err,
+        }
+    }
+}
+
 #[async_trait]
 impl Writer for Day5Error {
     async fn write(mut self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
-        match self {
-            Self::ParseError(_) | Self::NoMagicKeyword => {
+        let title = self.to_string();
+        let diagnostic = match self {
+            Self::ParseError(diagnostic) => {
+                res.status_code(StatusCode::BAD_REQUEST);
+                Some(diagnostic)
+            }
+            Self::MissingPackage(file) => {
                 res.status_code(StatusCode::BAD_REQUEST);
+                Some(ManifestDiagnostic {
+                    detail: "manifest has no [package] table".to_owned(),
+                    span: None,
+                    key: Some("package".to_owned()),
+                    file,
+                })
+            }
+            Self::PayloadError(_) | Self::MissingWorkspace | Self::NoMagicKeyword => {
+                res.status_code(StatusCode::BAD_REQUEST);
+                None
+            }
+            Self::IoError(_) => {
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                None
+            }
+            Self::UnsupportedMediaType => {
+                res.status_code(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                None
             }
             Self::NoOrders => {
                 res.status_code(StatusCode::NO_CONTENT);
+                None
             }
-        }
-        res.render(Text::Plain(self.to_string()));
+        };
+
+        let Some(diagnostic) = diagnostic else {
+            res.render(Text::Plain(title));
+            return;
+        };
+        res.render(Json(ManifestProblem {
+            type_: "about:blank".to_owned(),
+            title,
+            status: StatusCode::BAD_REQUEST.as_u16(),
+            detail: diagnostic.detail,
+            span: diagnostic.span,
+            key: diagnostic.key,
+            file: diagnostic.file,
+        }));
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
     }
 }
 
@@ -87,16 +608,93 @@ impl EndpointOutRegister for Day5Error {
         operation.responses.insert(
             StatusCode::BAD_REQUEST.as_str(),
             salvo::oapi::Response::new("Invalid Manifest or no magic keyword")
+                .add_content("text/plain", StatusError::to_schema(components))
+                .add_content(
+                    "application/problem+json",
+                    Content::new(ManifestProblem::to_schema(components)),
+                ),
+        );
+        operation.responses.insert(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE.as_str(),
+            salvo::oapi::Response::new("Content-Type is not TOML, YAML, JSON or multipart")
+                .add_content("text/plain", StatusError::to_schema(components)),
+        );
+        operation.responses.insert(
+            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
+            salvo::oapi::Response::new("Could not read uploaded manifests")
                 .add_content("text/plain", StatusError::to_schema(components)),
         );
     }
 }
 
-#[endpoint]
-async fn manifest_route(data: ManifestInput) -> Result<String, Day5Error> {
-    let manifest = Manifest::from_slice(&data.text)?;
-    let package = manifest.package.unwrap();
+/// Directory listing for manifests that only exist in memory, so completing them against a
+/// workspace doesn't go looking for targets on disk.
+struct NoFiles;
+
+impl AbstractFilesystem for NoFiles {
+    fn file_names_in(&self, _rel_path: &str) -> io::Result<HashSet<Box<str>>> {
+        Ok(HashSet::new())
+    }
+}
 
+/// Parses a workspace member and fills in its `workspace = true` fields from `root`.
+///
+/// Cargo does not inherit `package.metadata`, so a member opts in with
+/// `[package.metadata] workspace = true` to use `[workspace.metadata]` instead.
+fn parse_member(root: &Manifest, text: &[u8]) -> Result<Manifest, Day5Error> {
+    let mut member = ManifestFormat::Toml.parse(text)?;
+    member
+        .complete_from_abstract_filesystem(NoFiles, Some((root, Path::new(""))))
+        .map_err(|err| Day5Error::ParseError(ManifestDiagnostic::from_toml(&err, text)))?;
+    let package = member
+        .package
+        .as_mut()
+        .ok_or(Day5Error::MissingPackage(None))?;
+
+    let inherits_metadata = package
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get("workspace"))
+        .and_then(toml::Value::as_bool)
+        .unwrap_or(false);
+    if inherits_metadata {
+        package.metadata = root
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.metadata.clone());
+    }
+    Ok(member)
+}
+
+/// Parses a workspace upload into its root manifest and completed member manifests.
+fn parse_workspace(
+    root: &UploadedManifest,
+    members: &[UploadedManifest],
+) -> Result<(Manifest, Vec<Manifest>), Day5Error> {
+    let root_manifest = ManifestFormat::Toml
+        .parse(&root.text)
+        .map_err(|err| err.in_file(&root.name))?;
+    if root_manifest.workspace.is_none() {
+        return Err(Day5Error::MissingWorkspace);
+    }
+
+    // the root manifest is a member too, unless it's a virtual workspace
+    let members = std::iter::once(root)
+        .filter(|_| root_manifest.package.is_some())
+        .chain(members)
+        .map(|member| {
+            parse_member(&root_manifest, &member.text).map_err(|err| err.in_file(&member.name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((root_manifest, members))
+}
+
+/// Adds the orders of `package` to `report`, attributed to `attribution` if given.
+fn collect_orders(
+    package: &Package,
+    attribution: Option<&str>,
+    report: &mut OrderReport,
+) -> Result<(), Day5Error> {
     if !package
         .keywords
         .get()
@@ -107,32 +705,339 @@ async fn manifest_route(data: ManifestInput) -> Result<String, Day5Error> {
         return Err(Day5Error::NoMagicKeyword);
     }
 
-    let metadata = package.metadata.ok_or(Day5Error::NoOrders)?;
+    let metadata = package.metadata.as_ref().ok_or(Day5Error::NoOrders)?;
     let orders = metadata
         .get("orders")
         .ok_or(Day5Error::NoOrders)?
         .as_array()
         .ok_or(Day5Error::NoOrders)?;
 
-    let outputs: Vec<_> = orders
-        .iter()
-        .filter_map(|order| {
-            let order: Result<Order, _> = order.clone().try_into();
-            if let Ok(order) = order {
-                Some(format!("{}: {}", order.item, order.quantity))
-            } else {
-                None
+    for (index, order) in orders.iter().enumerate() {
+        let parsed: Result<Order, _> = order.clone().try_into();
+        match parsed {
+            Ok(order) => report.orders.push(Order {
+                package: attribution.map(str::to_owned),
+                ..order
+            }),
+            Err(err) => report.rejected.push(RejectedOrder {
+                index,
+                reason: err.to_string(),
+                package: attribution.map(str::to_owned),
+            }),
+        }
+    }
+    Ok(())
+}
+
+#[endpoint]
+async fn manifest_route(
+    data: ManifestInput,
+    rules: OrderRules,
+    req: &mut Request,
+) -> Result<ManifestOutput, Day5Error> {
+    let mut report = OrderReport::default();
+
+    match data {
+        ManifestInput::Single { format, text } => {
+            let manifest = format.parse(&text)?;
+            let package = manifest.package.ok_or(Day5Error::MissingPackage(None))?;
+            collect_orders(&package, None, &mut report)?;
+        }
+        ManifestInput::Workspace { root, members } => {
+            let (_, members) = parse_workspace(&root, &members)?;
+
+            let mut any_magic = false;
+            for package in members.iter().filter_map(|member| member.package.as_ref()) {
+                match collect_orders(package, Some(package.name()), &mut report) {
+                    Ok(()) | Err(Day5Error::NoOrders) => any_magic = true,
+                    Err(Day5Error::NoMagicKeyword) => {}
+                    Err(err) => return Err(err),
+                }
             }
-        })
-        .collect();
+            if !any_magic {
+                return Err(Day5Error::NoMagicKeyword);
+            }
+        }
+    }
+
+    rules.apply(&mut report);
+
+    let wants_json = req
+        .accept()
+        .iter()
+        .any(|mime| mime.essence_str() == "application/json");
+    if wants_json {
+        if report.orders.is_empty() && report.rejected.is_empty() && report.violations.is_empty() {
+            return Err(Day5Error::NoOrders);
+        }
+        return Ok(ManifestOutput::Json(report));
+    }
+
+    let mut outputs: Vec<_> = match &report.totals {
+        Some(totals) => totals
+            .iter()
+            .map(|total| format!("{}: {}", total.item, total.quantity))
+            .collect(),
+        None => report
+            .orders
+            .iter()
+            .map(|order| match &order.package {
+                Some(package) => format!("{}: {} ({package})", order.item, order.quantity),
+                None => format!("{}: {}", order.item, order.quantity),
+            })
+            .collect(),
+    };
+    if !report.violations.is_empty() {
+        outputs.push("Violations:".to_owned());
+        outputs.extend(
+            report
+                .violations
+                .iter()
+                .map(|OrderViolation { order, rule }| match &order.package {
+                    Some(package) => {
+                        format!("{}: {} ({package}, {rule})", order.item, order.quantity)
+                    }
+                    None => format!("{}: {} ({rule})", order.item, order.quantity),
+                }),
+        );
+    }
 
     if outputs.is_empty() {
         Err(Day5Error::NoOrders)
     } else {
-        Ok(outputs.join("\n"))
+        Ok(ManifestOutput::Text(outputs.join("\n")))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum DependencyKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum DependencySource {
+    Registry,
+    Git,
+    Path,
+    /// `workspace = true` that could not be resolved without the workspace root
+    Workspace,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DependencyEntry {
+    /// Key of the dependency in its table
+    pub name: String,
+    /// Crate the dependency resolves to, which differs from `name` when renamed
+    pub package: String,
+    pub kind: DependencyKind,
+    /// `cfg(...)` or target triple for platform-specific dependencies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub version: Option<String>,
+    pub source: DependencySource,
+    /// Git URL or path for non-registry dependencies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Workspace member declaring the dependency, for workspace uploads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    /// Whether the dependency is declared in the root's `[workspace.dependencies]` rather than
+    /// by a member
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub workspace: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum DependencyIssue {
+    GitSource,
+    PathSource,
+    WildcardVersion,
+    /// The same crate is pulled in under more than one name
+    DuplicateRename,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DependencyFinding {
+    pub name: String,
+    pub kind: DependencyKind,
+    pub issue: DependencyIssue,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub workspace: bool,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct DependencyReport {
+    pub dependencies: Vec<DependencyEntry>,
+    pub findings: Vec<DependencyFinding>,
+}
+
+fn is_wildcard(req: &str) -> bool {
+    req.split(',').any(|comparator| {
+        let comparator = comparator.trim();
+        comparator == "*" || comparator.ends_with(".*")
+    })
+}
+
+impl DependencyReport {
+    fn audit(&mut self, manifest: &Manifest, member: Option<&str>) {
+        let tables = [
+            (DependencyKind::Normal, None, &manifest.dependencies),
+            (DependencyKind::Dev, None, &manifest.dev_dependencies),
+            (DependencyKind::Build, None, &manifest.build_dependencies),
+        ];
+        let target_tables = manifest.target.iter().flat_map(|(target, deps)| {
+            [
+                (DependencyKind::Normal, Some(target), &deps.dependencies),
+                (DependencyKind::Dev, Some(target), &deps.dev_dependencies),
+                (
+                    DependencyKind::Build,
+                    Some(target),
+                    &deps.build_dependencies,
+                ),
+            ]
+        });
+
+        for (kind, target, deps) in tables.into_iter().chain(target_tables) {
+            for (name, dependency) in deps {
+                self.add(name, dependency, kind, target.cloned(), member, false);
+            }
+        }
+    }
+
+    fn add(
+        &mut self,
+        name: &str,
+        dependency: &Dependency,
+        kind: DependencyKind,
+        target: Option<String>,
+        member: Option<&str>,
+        workspace: bool,
+    ) {
+        let (source, location) = match dependency {
+            Dependency::Inherited(_) => (DependencySource::Workspace, None),
+            _ if dependency.git().is_some() => {
+                (DependencySource::Git, dependency.git().map(str::to_owned))
+            }
+            Dependency::Detailed(detail) if detail.path.is_some() => {
+                (DependencySource::Path, detail.path.clone())
+            }
+            _ => (DependencySource::Registry, None),
+        };
+        // git and path dependencies don't need a version, so only an explicit one can be a wildcard
+        let version = match dependency {
+            Dependency::Simple(version) => Some(version.clone()),
+            Dependency::Detailed(detail) => detail.version.clone(),
+            Dependency::Inherited(_) => None,
+        };
+        let wildcard = match (&version, source) {
+            (Some(version), _) => is_wildcard(version),
+            (None, DependencySource::Registry) => true,
+            (None, _) => false,
+        };
+        let package = dependency.package().unwrap_or(name).to_owned();
+
+        let mut issues = Vec::new();
+        match source {
+            DependencySource::Git => issues.push(DependencyIssue::GitSource),
+            DependencySource::Path => issues.push(DependencyIssue::PathSource),
+            DependencySource::Registry | DependencySource::Workspace => {}
+        }
+        if wildcard {
+            issues.push(DependencyIssue::WildcardVersion);
+        }
+
+        self.findings
+            .extend(issues.into_iter().map(|issue| DependencyFinding {
+                name: name.to_owned(),
+                kind,
+                issue,
+                member: member.map(str::to_owned),
+                workspace,
+            }));
+        self.dependencies.push(DependencyEntry {
+            name: name.to_owned(),
+            package,
+            kind,
+            target,
+            version,
+            source,
+            location,
+            member: member.map(str::to_owned),
+            workspace,
+        });
+    }
+
+    /// Flags every name a member uses for a crate it also pulls in under another name, across
+    /// all of its dependency tables.
+    fn flag_duplicate_renames(&mut self) {
+        /// A member, the workspace table or a single manifest, along with a crate it pulls in.
+        type Declarer<'a> = (Option<&'a str>, bool, &'a str);
+        fn declarer(entry: &DependencyEntry) -> Declarer<'_> {
+            (
+                entry.member.as_deref(),
+                entry.workspace,
+                entry.package.as_str(),
+            )
+        }
+        let mut names: BTreeMap<Declarer<'_>, BTreeSet<&str>> = BTreeMap::new();
+        for entry in &self.dependencies {
+            names
+                .entry(declarer(entry))
+                .or_default()
+                .insert(entry.name.as_str());
+        }
+        let duplicates = self
+            .dependencies
+            .iter()
+            .filter(|entry| names[&declarer(entry)].len() > 1)
+            .map(|entry| DependencyFinding {
+                name: entry.name.clone(),
+                kind: entry.kind,
+                issue: DependencyIssue::DuplicateRename,
+                member: entry.member.clone(),
+                workspace: entry.workspace,
+            })
+            .collect::<Vec<_>>();
+        self.findings.extend(duplicates);
+    }
+}
+
+#[endpoint(status_codes(200, 400, 415, 500))]
+async fn dependencies_route(data: ManifestInput) -> Result<Json<DependencyReport>, Day5Error> {
+    let mut report = DependencyReport::default();
+    match data {
+        ManifestInput::Single { format, text } => {
+            let manifest = format.parse(&text)?;
+            report.audit(&manifest, None);
+        }
+        ManifestInput::Workspace { root, members } => {
+            let (root, members) = parse_workspace(&root, &members)?;
+            if let Some(workspace) = &root.workspace {
+                for (name, dependency) in &workspace.dependencies {
+                    report.add(name, dependency, DependencyKind::Normal, None, None, true);
+                }
+            }
+            for member in &members {
+                report.audit(
+                    member,
+                    member.package.as_ref().map(|package| package.name()),
+                );
+            }
+        }
     }
+    report.flag_duplicate_renames();
+    Ok(Json(report))
 }
 
 pub fn get_router() -> Router {
-    Router::new().push(Router::with_path("/5/manifest").post(manifest_route))
+    Router::new()
+        .push(Router::with_path("/5/manifest").post(manifest_route))
+        .push(Router::with_path("/5/dependencies").post(dependencies_route))
 }
@@ -1,67 +1,86 @@
-use std::{
-    sync::atomic::{AtomicU64, Ordering},
-    time::Duration,
-};
+use std::{str::FromStr, sync::LazyLock, time::Duration};
 
 use salvo::{
-    http::ParseError,
-    oapi::{schema::OneOf, BasicType, Content, Object, RequestBody, Schema},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderValue, ParseError,
+    },
+    oapi::{extract::QueryParam, schema::OneOf, BasicType, Content, Object, RequestBody, Schema},
     prelude::*,
     Extractible,
 };
 use serde::{Deserialize, Serialize};
 
-static COUNTER: AtomicU64 = AtomicU64::new(5);
-
-fn saturating_dec_atm(val: &AtomicU64) -> u64 {
-    loop {
-        let current = val.load(Ordering::Relaxed);
-        if current > 0 {
-            if let Ok(current) =
-                val.compare_exchange(current, current - 1, Ordering::Relaxed, Ordering::Relaxed)
-            {
-                // successfully decremented
-                return current;
-            }
-            // decrement did not succeed. try operation from beginning again.
-        } else {
-            // is 0 already. can't subtract more.
-            return 0;
-        }
-    }
+use crate::rate_limit::{
+    env_var, too_many_requests_response, with_rate_limit_headers, LimitConfig, RateLimitError,
+    RateLimiter,
+};
+
+/// Limiter settings, read from the environment at startup.
+#[derive(Debug)]
+struct MilkConfig {
+    /// `MILK_BUCKET_CAPACITY` and `MILK_REFILL_INTERVAL_MS`
+    limit: LimitConfig,
+    /// `MILK_BATCH_COST`, whether a batch of conversions takes one milk or one per item
+    batch_cost: BatchCost,
+    /// `MILK_MAX_BATCH_SIZE`, most conversions accepted in one batch
+    max_batch_size: usize,
+    /// `MILK_ADMIN_TOKEN`, bearer token for `/9/refill`. Refills are disabled without one.
+    admin_token: Option<String>,
 }
 
-fn saturating_inc_atm(val: &AtomicU64, max: u64) -> u64 {
-    loop {
-        let current = val.load(Ordering::Relaxed);
-        if current < max {
-            if let Ok(current) =
-                val.compare_exchange(current, current + 1, Ordering::Relaxed, Ordering::Relaxed)
-            {
-                // successfully incremented
-                return current;
-            }
-            // incremented did not succeed. try operation from beginning again.
-        } else {
-            // is max already. can't subtract more.
-            return max;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchCost {
+    Request,
+    Item,
+}
+
+impl FromStr for BatchCost {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "request" => Ok(Self::Request),
+            "item" => Ok(Self::Item),
+            _ => Err(()),
         }
     }
 }
 
-#[handler]
-async fn rate_limit_middleware(res: &mut Response) {
-    if saturating_dec_atm(&COUNTER) == 0 {
-        res.status_code(StatusCode::TOO_MANY_REQUESTS);
-        res.render(Text::Plain("No milk available\n"));
-        return;
+impl MilkConfig {
+    fn from_env() -> Self {
+        Self {
+            limit: LimitConfig::from_env("MILK", 5, Duration::from_secs(1)),
+            batch_cost: env_var("MILK_BATCH_COST", BatchCost::Request),
+            max_batch_size: env_var("MILK_MAX_BATCH_SIZE", 100),
+            admin_token: std::env::var("MILK_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+        }
     }
 }
 
+static CONFIG: LazyLock<MilkConfig> = LazyLock::new(MilkConfig::from_env);
+
+const NO_MILK: &str = "No milk available\n";
+
+static MILK_LIMITER: LazyLock<RateLimiter> = LazyLock::new(|| {
+    RateLimiter::new("milk", CONFIG.limit.capacity, CONFIG.limit.refill_interval).message(NO_MILK)
+});
+
 #[derive(Debug, ToSchema)]
 enum MilkInput {
     Empty,
     Convert(ConvertInput),
+    /// Items are parsed one by one so a bad item doesn't fail the others
+    Batch(Vec<serde_json::Value>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MilkRequest {
+    Batch(Vec<serde_json::Value>),
+    Single(ConvertInput),
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
@@ -88,6 +107,15 @@ struct Gallons {
     pub gallons: f64,
 }
 
+/// Conversion between any two units in [`UNITS`] of the same dimension.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct UnitConversion {
+    pub value: f64,
+    pub from: String,
+    pub to: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(untagged)]
 enum ConvertInput {
@@ -95,6 +123,197 @@ enum ConvertInput {
     Gallons(Gallons),
     Litres(Litres),
     Pints(Pints),
+    Units(UnitConversion),
+}
+
+/// Result of a [`UnitConversion`].
+#[derive(Debug, Serialize, ToSchema)]
+struct Quantity {
+    pub value: f64,
+    pub unit: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+enum ConvertOutput {
+    Liters(Liters),
+    Gallons(Gallons),
+    Litres(Litres),
+    Pints(Pints),
+    Quantity(Quantity),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Volume,
+    Mass,
+}
+
+#[derive(Debug)]
+struct Unit {
+    /// Accepted spellings, in the normalised form produced by [`Unit::find`]
+    names: &'static [&'static str],
+    dimension: Dimension,
+    /// Size of the unit in liters, or in grams for mass
+    factor: f64,
+}
+
+const GALLON_TO_LITER: f64 = 3.78541;
+const PINT_TO_LITRE: f64 = 0.568261;
+
+/// US customary units unless marked `uk`. Only the `{"pints"}` and `{"litres"}` inputs use
+/// imperial pints, as they always have.
+const UNITS: &[Unit] = &[
+    Unit {
+        names: &["l", "liter", "liters", "litre", "litres"],
+        dimension: Dimension::Volume,
+        factor: 1.0,
+    },
+    Unit {
+        names: &[
+            "ml",
+            "milliliter",
+            "milliliters",
+            "millilitre",
+            "millilitres",
+        ],
+        dimension: Dimension::Volume,
+        factor: 0.001,
+    },
+    Unit {
+        names: &["gal", "gal us", "us gal", "gallon", "gallons"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER,
+    },
+    Unit {
+        names: &["gal uk", "uk gal", "imperial gallon", "imperial gallons"],
+        dimension: Dimension::Volume,
+        factor: PINT_TO_LITRE * 8.0,
+    },
+    Unit {
+        names: &["qt", "qt us", "us qt", "quart", "quarts"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 4.0,
+    },
+    Unit {
+        names: &["qt uk", "uk qt", "imperial quart", "imperial quarts"],
+        dimension: Dimension::Volume,
+        factor: PINT_TO_LITRE * 2.0,
+    },
+    Unit {
+        names: &["pt", "pt us", "us pt", "pint", "pints"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 8.0,
+    },
+    Unit {
+        names: &["pt uk", "uk pt", "imperial pint", "imperial pints"],
+        dimension: Dimension::Volume,
+        factor: PINT_TO_LITRE,
+    },
+    Unit {
+        names: &["cup", "cups"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 16.0,
+    },
+    Unit {
+        names: &[
+            "fl oz",
+            "fl oz us",
+            "us fl oz",
+            "fluid ounce",
+            "fluid ounces",
+        ],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 128.0,
+    },
+    Unit {
+        names: &[
+            "fl oz uk",
+            "uk fl oz",
+            "imperial fluid ounce",
+            "imperial fluid ounces",
+        ],
+        dimension: Dimension::Volume,
+        factor: PINT_TO_LITRE / 20.0,
+    },
+    Unit {
+        names: &["tbsp", "tablespoon", "tablespoons"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 256.0,
+    },
+    Unit {
+        names: &["tsp", "teaspoon", "teaspoons"],
+        dimension: Dimension::Volume,
+        factor: GALLON_TO_LITER / 768.0,
+    },
+    Unit {
+        names: &["g", "gram", "grams"],
+        dimension: Dimension::Mass,
+        factor: 1.0,
+    },
+    Unit {
+        names: &["kg", "kilogram", "kilograms"],
+        dimension: Dimension::Mass,
+        factor: 1000.0,
+    },
+    Unit {
+        names: &["oz", "ounce", "ounces"],
+        dimension: Dimension::Mass,
+        factor: 28.349523125,
+    },
+    Unit {
+        names: &["lb", "lbs", "pound", "pounds"],
+        dimension: Dimension::Mass,
+        factor: 453.59237,
+    },
+];
+
+impl Unit {
+    /// Looks a unit up ignoring case, with `_`, `-` and `.` treated as spaces, so `fl_oz_uk` and
+    /// `Fl. Oz.` both work.
+    fn find(name: &str) -> Result<&'static Self, Day9Error> {
+        let normalised = name
+            .to_lowercase()
+            .replace(['_', '-', '.'], " ")
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        UNITS
+            .iter()
+            .find(|unit| unit.names.contains(&normalised.as_str()))
+            .ok_or_else(|| Day9Error::UnknownUnit(name.to_owned()))
+    }
+}
+
+fn convert(value: f64, from: &str, to: &str) -> Result<f64, Day9Error> {
+    let (from_unit, to_unit) = (Unit::find(from)?, Unit::find(to)?);
+    if from_unit.dimension != to_unit.dimension {
+        return Err(Day9Error::IncompatibleUnits(from.to_owned(), to.to_owned()));
+    }
+    Ok(value * from_unit.factor / to_unit.factor)
+}
+
+impl ConvertInput {
+    fn convert(self) -> Result<ConvertOutput, Day9Error> {
+        Ok(match self {
+            Self::Liters(Liters { liters }) => ConvertOutput::Gallons(Gallons {
+                gallons: convert(liters, "liters", "gallons")?,
+            }),
+            Self::Gallons(Gallons { gallons }) => ConvertOutput::Liters(Liters {
+                liters: convert(gallons, "gallons", "liters")?,
+            }),
+            Self::Litres(Litres { litres }) => ConvertOutput::Pints(Pints {
+                pints: convert(litres, "litres", "pt uk")?,
+            }),
+            Self::Pints(Pints { pints }) => ConvertOutput::Litres(Litres {
+                litres: convert(pints, "pt uk", "litres")?,
+            }),
+            Self::Units(UnitConversion { value, from, to }) => ConvertOutput::Quantity(Quantity {
+                value: convert(value, &from, &to)?,
+                unit: to,
+            }),
+        })
+    }
 }
 
 impl<'ex> Extractible<'ex> for MilkInput {
@@ -108,8 +327,11 @@ impl<'ex> Extractible<'ex> for MilkInput {
     ) -> Result<Self, impl Writer + Send + std::fmt::Debug + 'static> {
         match req.content_type() {
             Some(mime) if mime.essence_str() == "application/json" => {
-                let bruh: ConvertInput = req.parse_json::<ConvertInput>().await?;
-                Ok::<Self, Day9Error>(Self::Convert(bruh))
+                let bruh = req.parse_json::<MilkRequest>().await?;
+                Ok::<Self, Day9Error>(match bruh {
+                    MilkRequest::Single(input) => Self::Convert(input),
+                    MilkRequest::Batch(items) => Self::Batch(items),
+                })
             }
             _ => Ok(Self::Empty),
         }
@@ -130,7 +352,11 @@ impl EndpointArgRegister for MilkInput {
                 )
                 .add_content(
                     "application/json",
-                    Content::new(ConvertInput::to_schema(components)),
+                    Content::new(Schema::OneOf(
+                        OneOf::new()
+                            .item(ConvertInput::to_schema(components))
+                            .item(Vec::<ConvertInput>::to_schema(components)),
+                    )),
                 ),
         );
     }
@@ -140,43 +366,118 @@ impl EndpointArgRegister for MilkInput {
 enum Day9Error {
     #[error("parse error: {0}")]
     ParseError(#[from] ParseError),
+
+    #[error("unknown unit: {0}")]
+    UnknownUnit(String),
+
+    #[error("cannot convert {0} to {1}")]
+    IncompatibleUnits(String, String),
+
+    #[error("invalid conversion: {0}")]
+    InvalidItem(#[from] serde_json::Error),
+
+    #[error("batch has more than {0} conversions")]
+    BatchTooLarge(usize),
+
+    #[error(transparent)]
+    RateLimitError(#[from] RateLimitError),
+
+    #[error("admin token required")]
+    Unauthorized,
+
+    #[error("not allowed to refill")]
+    Forbidden,
 }
 
 #[async_trait]
 impl Writer for Day9Error {
     async fn write(self, _req: &mut Request, _depot: &mut Depot, res: &mut Response) {
         match self {
-            Self::ParseError(_) => {
+            Self::ParseError(_)
+            | Self::UnknownUnit(_)
+            | Self::IncompatibleUnits(..)
+            | Self::InvalidItem(_) => {
                 res.status_code(StatusCode::BAD_REQUEST);
             }
+            Self::BatchTooLarge(_) => {
+                res.status_code(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Self::RateLimitError(_) => {
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            Self::Unauthorized => {
+                res.status_code(StatusCode::UNAUTHORIZED);
+                res.headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            Self::Forbidden => {
+                res.status_code(StatusCode::FORBIDDEN);
+            }
         }
         res.render(Text::Plain(self.to_string()));
     }
 }
 
 impl EndpointOutRegister for Day9Error {
-    fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        RateLimitError::register(components, operation);
         operation.responses.insert(
             StatusCode::TOO_MANY_REQUESTS.as_str(),
-            salvo::oapi::Response::new("no milk").add_content(
-                "text/plain",
-                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
-            ),
+            too_many_requests_response("no milk"),
         );
         operation.responses.insert(
             StatusCode::BAD_REQUEST.as_str(),
-            salvo::oapi::Response::new("no milk").add_content(
-                "text/plain",
-                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            with_rate_limit_headers(
+                salvo::oapi::Response::new("invalid conversion").add_content(
+                    "text/plain",
+                    Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+                ),
+            ),
+        );
+        operation.responses.insert(
+            StatusCode::PAYLOAD_TOO_LARGE.as_str(),
+            with_rate_limit_headers(
+                salvo::oapi::Response::new("too many conversions in one batch").add_content(
+                    "text/plain",
+                    Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+                ),
             ),
         );
+        operation.responses.insert(
+            StatusCode::UNAUTHORIZED.as_str(),
+            salvo::oapi::Response::new("missing admin token"),
+        );
+        operation.responses.insert(
+            StatusCode::FORBIDDEN.as_str(),
+            salvo::oapi::Response::new("wrong admin token, or refills are disabled"),
+        );
+    }
+}
+
+/// One result in a batch, either the conversion or why it failed.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+enum BatchItem {
+    Converted(ConvertOutput),
+    Error { error: String },
+}
+
+impl From<Result<ConvertOutput, Day9Error>> for BatchItem {
+    fn from(result: Result<ConvertOutput, Day9Error>) -> Self {
+        match result {
+            Ok(output) => Self::Converted(output),
+            Err(err) => Self::Error {
+                error: err.to_string(),
+            },
+        }
     }
 }
 
 #[derive(Debug, ToSchema)]
 enum MilkOutput {
     String(String),
-    Convert(ConvertInput),
+    Convert(ConvertOutput),
+    Batch(Vec<BatchItem>),
 }
 
 #[async_trait]
@@ -189,6 +490,9 @@ impl Writer for MilkOutput {
             Self::Convert(convert) => {
                 res.render(Json(convert));
             }
+            Self::Batch(items) => {
+                res.render(Json(items));
+            }
         }
     }
 }
@@ -197,57 +501,98 @@ impl EndpointOutRegister for MilkOutput {
     fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
         operation.responses.insert(
             StatusCode::OK.as_str(),
-            salvo::oapi::Response::new("success").add_content(
-                "text/plain",
-                Content::new(Schema::OneOf(
-                    OneOf::new()
-                        .item(Schema::Object(Object::new().schema_type(BasicType::String)))
-                        .item(ConvertInput::to_schema(components)),
-                )),
+            with_rate_limit_headers(
+                salvo::oapi::Response::new("success").add_content(
+                    "text/plain",
+                    Content::new(Schema::OneOf(
+                        OneOf::new()
+                            .item(Schema::Object(Object::new().schema_type(BasicType::String)))
+                            .item(ConvertOutput::to_schema(components))
+                            .item(Vec::<BatchItem>::to_schema(components)),
+                    )),
+                ),
             ),
         );
     }
 }
 
-const GALLON_TO_LITER: f64 = 3.78541;
-const PINT_TO_LITRE: f64 = 0.568261;
-
-#[endpoint]
-async fn milk_route(inputs: MilkInput) -> Result<MilkOutput, Day9Error> {
+#[endpoint(status_codes(200, 400, 413, 429, 500))]
+async fn milk_route(
+    req: &mut Request,
+    res: &mut Response,
+    inputs: MilkInput,
+) -> Result<MilkOutput, Day9Error> {
     match inputs {
         MilkInput::Empty => Ok(MilkOutput::String("Milk withdrawn\n".to_owned())),
-        MilkInput::Convert(ConvertInput::Liters(Liters { liters })) => {
-            Ok(MilkOutput::Convert(ConvertInput::Gallons(Gallons {
-                gallons: liters / GALLON_TO_LITER,
-            })))
-        }
-        MilkInput::Convert(ConvertInput::Gallons(Gallons { gallons })) => {
-            Ok(MilkOutput::Convert(ConvertInput::Liters(Liters {
-                liters: gallons * GALLON_TO_LITER,
-            })))
-        }
-        MilkInput::Convert(ConvertInput::Litres(Litres { litres })) => {
-            Ok(MilkOutput::Convert(ConvertInput::Pints(Pints {
-                pints: litres / PINT_TO_LITRE,
-            })))
-        }
-        MilkInput::Convert(ConvertInput::Pints(Pints { pints })) => {
-            Ok(MilkOutput::Convert(ConvertInput::Litres(Litres {
-                litres: pints * PINT_TO_LITRE,
-            })))
+        MilkInput::Convert(input) => Ok(MilkOutput::Convert(input.convert()?)),
+        MilkInput::Batch(items) => {
+            if items.len() > CONFIG.max_batch_size {
+                return Err(Day9Error::BatchTooLarge(CONFIG.max_batch_size));
+            }
+            let mut results = Vec::with_capacity(items.len());
+            for (index, item) in items.into_iter().enumerate() {
+                // the limiter hoop has already taken milk for the first item
+                if CONFIG.batch_cost == BatchCost::Item && index > 0 {
+                    let quota = MILK_LIMITER.withdraw(req).await?;
+                    quota.write_headers(res);
+                    if !quota.allowed {
+                        results.push(BatchItem::Error {
+                            error: NO_MILK.trim_end().to_owned(),
+                        });
+                        continue;
+                    }
+                }
+                let result = serde_json::from_value::<ConvertInput>(item)
+                    .map_err(Day9Error::from)
+                    .and_then(ConvertInput::convert);
+                results.push(result.into());
+            }
+            Ok(MilkOutput::Batch(results))
         }
     }
 }
 
+/// Compares without returning early, so the time taken doesn't give away how much of the token
+/// was right.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Admin endpoint topping a bucket back up to capacity, or every bucket if `key` is not given.
+/// Needs `Authorization: Bearer <MILK_ADMIN_TOKEN>`.
+#[endpoint(status_codes(200, 401, 403, 500))]
+async fn refill_route(
+    key: QueryParam<String, false>,
+    req: &mut Request,
+) -> Result<&'static str, Day9Error> {
+    let given = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(Day9Error::Unauthorized)?;
+    let expected = CONFIG.admin_token.as_deref().ok_or(Day9Error::Forbidden)?;
+    if !token_matches(given, expected) {
+        return Err(Day9Error::Forbidden);
+    }
+
+    MILK_LIMITER.refill(key.as_deref()).await?;
+    Ok("")
+}
+
 pub fn get_router() -> Router {
-    tokio::spawn(async {
-        let mut interval = tokio::time::interval(Duration::from_secs(1));
-        loop {
-            interval.tick().await;
-            saturating_inc_atm(&COUNTER, 5);
-        }
-    });
+    // fail at startup rather than on the first request if the config is invalid
+    LazyLock::force(&CONFIG);
     Router::new()
-        .hoop(rate_limit_middleware)
-        .push(Router::with_path("/9/milk").post(milk_route))
+        .push(
+            Router::new()
+                .hoop(MILK_LIMITER.clone())
+                .push(Router::with_path("/9/milk").post(milk_route)),
+        )
+        .push(Router::with_path("/9/refill").post(refill_route))
 }
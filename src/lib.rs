@@ -1,2 +1,3 @@
 pub mod days;
 pub mod db;
+pub mod rate_limit;
@@ -0,0 +1,848 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use salvo::{
+    http::{
+        header::{HeaderName, FORWARDED, RETRY_AFTER},
+        HeaderValue,
+    },
+    oapi::{BasicType, Content, Header, Object, Schema},
+    prelude::*,
+};
+
+use crate::db::DB_POOL;
+
+/// Full buckets are forgotten this often. A full bucket behaves the same as a fresh one, so this
+/// loses nothing.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most buckets a limiter keeps in memory. Past this, the bucket closest to full is forgotten.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Header identifying a client. Clients without a known key are identified by their IP address.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Comma-separated API keys that get a bucket of their own.
+const API_KEYS_VAR: &str = "RATE_LIMIT_API_KEYS";
+
+static API_KEYS: LazyLock<HashSet<String>> = LazyLock::new(|| {
+    std::env::var(API_KEYS_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_owned)
+        .collect()
+});
+
+/// Header in which a trusted reverse proxy passes on the client's address, such as
+/// `x-forwarded-for` or `forwarded`. Only set this if every request comes through that proxy,
+/// since clients can send the header themselves.
+const CLIENT_IP_HEADER_VAR: &str = "RATE_LIMIT_CLIENT_IP_HEADER";
+
+static CLIENT_IP_HEADER: LazyLock<Option<HeaderName>> = LazyLock::new(|| {
+    let name = std::env::var(CLIENT_IP_HEADER_VAR).ok()?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(
+        HeaderName::from_bytes(name.to_ascii_lowercase().as_bytes())
+            .unwrap_or_else(|_| panic!("{CLIENT_IP_HEADER_VAR} is not valid: {name:?}")),
+    )
+});
+
+/// Selects where buckets are stored, `memory` (the default) or `postgres`.
+const BACKEND_VAR: &str = "RATE_LIMIT_BACKEND";
+
+const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+const RATELIMIT_RESET: &str = "ratelimit-reset";
+
+/// Reads a setting from the environment, panicking at startup if it is set but not valid.
+pub fn env_var<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} is not valid: {value:?}")),
+        Err(_) => default,
+    }
+}
+
+/// Bucket settings for a [`RateLimiter`], read from the environment at startup.
+#[derive(Debug, Clone, Copy)]
+pub struct LimitConfig {
+    /// `<PREFIX>_BUCKET_CAPACITY`, most requests a bucket can hold
+    pub capacity: u64,
+    /// `<PREFIX>_REFILL_INTERVAL_MS`, time for one request to be added back to a bucket
+    pub refill_interval: Duration,
+}
+
+impl LimitConfig {
+    /// Reads the settings starting with `prefix`, falling back to the given defaults.
+    pub fn from_env(prefix: &str, capacity: u64, refill_interval: Duration) -> Self {
+        let capacity = env_var(&format!("{prefix}_BUCKET_CAPACITY"), capacity);
+        let refill_interval = Duration::from_millis(env_var(
+            &format!("{prefix}_REFILL_INTERVAL_MS"),
+            u64::try_from(refill_interval.as_millis()).unwrap_or(u64::MAX),
+        ));
+        assert!(
+            !refill_interval.is_zero(),
+            "{prefix}_REFILL_INTERVAL_MS must be positive"
+        );
+        Self {
+            capacity,
+            refill_interval,
+        }
+    }
+}
+
+/// Identifies the client a request counts against.
+pub type KeyExtractor = fn(&Request) -> String;
+
+/// Keys clients by their `x-api-key` header if it is one of the configured keys, or by IP
+/// address otherwise. Unknown keys are ignored so clients can't get fresh buckets by making
+/// them up.
+///
+/// Behind a reverse proxy, such as Shuttle's, every client has the proxy's address unless
+/// `RATE_LIMIT_CLIENT_IP_HEADER` names the header the proxy puts the client's address in.
+pub fn client_key(req: &Request) -> String {
+    if let Some(key) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|key| API_KEYS.contains(*key))
+    {
+        return format!("key:{key}");
+    }
+    ip_key(req)
+}
+
+/// Keys clients by IP address only, taken from `RATE_LIMIT_CLIENT_IP_HEADER` if it is set and
+/// present.
+pub fn ip_key(req: &Request) -> String {
+    let addr = req.remote_addr();
+    let ip = forwarded_ip(req)
+        .or_else(|| addr.as_ipv4().map(|addr| IpAddr::V4(*addr.ip())))
+        .or_else(|| addr.as_ipv6().map(|addr| IpAddr::V6(*addr.ip())));
+    match ip {
+        Some(ip) => format!("ip:{ip}"),
+        // unix sockets and unknown peers all share one bucket
+        None => "unknown".to_owned(),
+    }
+}
+
+/// The client's address as passed on by the trusted proxy.
+fn forwarded_ip(req: &Request) -> Option<IpAddr> {
+    let header = CLIENT_IP_HEADER.as_ref()?;
+    // the proxy appends the address it saw, so only the last entry can be trusted
+    let last = req
+        .headers()
+        .get_all(header)
+        .iter()
+        .last()?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?;
+    if *header == FORWARDED {
+        parse_addr(forwarded_for(last)?)
+    } else {
+        parse_addr(last)
+    }
+}
+
+/// The `for` parameter of one `Forwarded` element, such as `for=192.0.2.1;proto=https`.
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        name.eq_ignore_ascii_case("for").then_some(value)
+    })
+}
+
+/// An address with or without a port, as in `192.0.2.1`, `"[2001:db8::1]:4711"` or
+/// `2001:db8::1`.
+fn parse_addr(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<IpAddr>()
+        .or_else(|_| value.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or_else(|| value.strip_prefix('[')?.strip_suffix(']')?.parse().ok())
+}
+
+/// A client's bucket after a withdrawal, as reported in the `RateLimit-*` headers.
+#[derive(Debug)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the next token is added, if the bucket is empty
+    pub retry_after: Duration,
+    /// Time until the bucket is full again
+    pub reset: Duration,
+}
+
+/// Whole seconds, rounded up so clients never retry too early.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+impl Quota {
+    /// Works out the quota from the time left until the bucket is full, after the withdrawal.
+    fn new(capacity: u64, refill_interval: Duration, allowed: bool, backlog: Duration) -> Self {
+        let window = window(capacity, refill_interval);
+        let remaining = window.saturating_sub(backlog).as_nanos() / refill_interval.as_nanos();
+        Self {
+            allowed,
+            limit: capacity,
+            remaining: u64::try_from(remaining).unwrap_or(u64::MAX),
+            retry_after: if allowed {
+                Duration::ZERO
+            } else {
+                (backlog + refill_interval).saturating_sub(window)
+            },
+            reset: backlog,
+        }
+    }
+
+    pub fn write_headers(&self, res: &mut Response) {
+        let headers = res.headers_mut();
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATELIMIT_RESET, HeaderValue::from(ceil_secs(self.reset)));
+        if !self.allowed {
+            headers.insert(
+                RETRY_AFTER,
+                HeaderValue::from(ceil_secs(self.retry_after).max(1)),
+            );
+        }
+    }
+}
+
+/// Time for an empty bucket to fill up.
+fn window(capacity: u64, refill_interval: Duration) -> Duration {
+    refill_interval.saturating_mul(u32::try_from(capacity).unwrap_or(u32::MAX))
+}
+
+/// Adds the `RateLimit-*` headers sent by [`RateLimiter`] to a documented response.
+pub fn with_rate_limit_headers(response: salvo::oapi::Response) -> salvo::oapi::Response {
+    response
+        .add_header(
+            "RateLimit-Limit",
+            integer_header().description("Capacity of the bucket"),
+        )
+        .add_header(
+            "RateLimit-Remaining",
+            integer_header().description("Requests left in the bucket"),
+        )
+        .add_header(
+            "RateLimit-Reset",
+            integer_header().description("Seconds until the bucket is full"),
+        )
+}
+
+/// Documents the 429 response sent by [`RateLimiter`] when a bucket is empty.
+pub fn too_many_requests_response(description: &str) -> salvo::oapi::Response {
+    with_rate_limit_headers(
+        salvo::oapi::Response::new(description)
+            .add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            )
+            .add_header(
+                "Retry-After",
+                integer_header().description("Seconds until a request will be allowed"),
+            ),
+    )
+}
+
+/// Output of an endpoint behind a [`RateLimiter`] hoop, written as is but documented along with
+/// the 429 response the hoop can send instead.
+#[derive(Debug)]
+pub struct RateLimited<T>(pub T);
+
+#[async_trait]
+impl<T: Writer + Send> Writer for RateLimited<T> {
+    async fn write(self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        self.0.write(req, depot, res).await;
+    }
+}
+
+impl<T: EndpointOutRegister> EndpointOutRegister for RateLimited<T> {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        T::register(components, operation);
+        operation.responses.insert(
+            StatusCode::TOO_MANY_REQUESTS.as_str(),
+            too_many_requests_response("rate limit exceeded"),
+        );
+    }
+}
+
+fn integer_header() -> Header {
+    Header::new(Schema::Object(
+        Object::new().schema_type(BasicType::Integer),
+    ))
+}
+
+/// Source of time for the limiter, so it can be driven by a fake clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+}
+
+/// Token buckets implemented with GCRA. Rather than counting tokens, each client stores the time
+/// at which its bucket will be full again, and refills are worked out from the clock when a
+/// request comes in. Clients without an entry have a full bucket.
+#[derive(Debug)]
+pub struct Limiter<C = SystemClock> {
+    capacity: u64,
+    refill_interval: Duration,
+    clock: C,
+    full_at: HashMap<String, Instant>,
+    last_sweep: Instant,
+}
+
+impl<C: Clock> Limiter<C> {
+    /// A limiter allowing bursts of `capacity` requests, with one more allowed every
+    /// `refill_interval`.
+    pub fn new(capacity: u64, refill_interval: Duration, clock: C) -> Self {
+        assert!(
+            !refill_interval.is_zero(),
+            "refill interval must be positive"
+        );
+        let last_sweep = clock.now();
+        Self {
+            capacity,
+            refill_interval,
+            clock,
+            full_at: HashMap::new(),
+            last_sweep,
+        }
+    }
+
+    /// Takes one token from the client's bucket, if there is any.
+    pub fn withdraw(&mut self, key: String) -> Quota {
+        let now = self.clock.now();
+        self.sweep(now);
+
+        let full_at = self.full_at.get(&key).copied().unwrap_or(now).max(now);
+        let next_full_at = full_at + self.refill_interval;
+
+        let allowed = next_full_at - now <= window(self.capacity, self.refill_interval);
+        let full_at = if allowed {
+            if !self.full_at.contains_key(&key) && self.full_at.len() >= MAX_BUCKETS {
+                self.evict(now);
+            }
+            self.full_at.insert(key, next_full_at);
+            next_full_at
+        } else {
+            full_at
+        };
+        Quota::new(self.capacity, self.refill_interval, allowed, full_at - now)
+    }
+
+    /// Fills the client's bucket back to capacity, or every bucket if `key` is `None`.
+    pub fn refill(&mut self, key: Option<&str>) {
+        match key {
+            Some(key) => {
+                self.full_at.remove(key);
+            }
+            None => self.full_at.clear(),
+        }
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        if now - self.last_sweep < SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = now;
+        self.full_at.retain(|_, full_at| *full_at > now);
+    }
+
+    /// Makes room for one more bucket, forgetting full buckets first and then the one closest
+    /// to full, which loses the least.
+    fn evict(&mut self, now: Instant) {
+        self.full_at.retain(|_, full_at| *full_at > now);
+        if self.full_at.len() < MAX_BUCKETS {
+            return;
+        }
+        let closest = self
+            .full_at
+            .iter()
+            .min_by_key(|(_, full_at)| **full_at)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = closest {
+            self.full_at.remove(&key);
+        }
+    }
+}
+
+/// Whole microseconds, for binding durations as Postgres intervals.
+fn micros(duration: Duration) -> i64 {
+    i64::try_from(duration.as_micros()).unwrap_or(i64::MAX)
+}
+
+/// The same buckets as [`Limiter`], kept in Postgres so they are shared by every replica. Each
+/// withdrawal is a single upsert, and only the database's clock is used so replicas with skewed
+/// clocks still agree.
+#[derive(Debug)]
+pub struct PgLimiter {
+    /// Prefix keeping this limiter's rows apart from other limiters'
+    name: &'static str,
+    capacity: u64,
+    refill_interval: Duration,
+    last_sweep: Mutex<Instant>,
+}
+
+impl PgLimiter {
+    pub fn new(name: &'static str, capacity: u64, refill_interval: Duration) -> Self {
+        assert!(
+            !refill_interval.is_zero(),
+            "refill interval must be positive"
+        );
+        Self {
+            name,
+            capacity,
+            refill_interval,
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Takes one token from the client's bucket, if there is any.
+    pub async fn withdraw(&self, key: &str) -> Result<Quota, RateLimitError> {
+        let pool = DB_POOL.get().ok_or(RateLimitError::NoDatabase)?;
+        self.sweep(pool).await?;
+
+        let key = format!("{}:{key}", self.name);
+        let interval = micros(self.refill_interval);
+        let window = micros(window(self.capacity, self.refill_interval));
+        // both the insert and the update only happen if the bucket has a token left, so no row
+        // means denied
+        let withdrawn = sqlx::query_as::<_, (DateTime<Utc>, DateTime<Utc>)>(
+            "insert into rate_limit_buckets (key, full_at)
+            select $1, now() + $2 * interval '1 microsecond'
+            where $2 <= $3
+            on conflict (key) do update
+            set full_at = greatest(rate_limit_buckets.full_at, now()) + $2 * interval '1 microsecond'
+            where greatest(rate_limit_buckets.full_at, now()) + $2 * interval '1 microsecond'
+                <= now() + $3 * interval '1 microsecond'
+            returning full_at, now()",
+        )
+        .bind(&key)
+        .bind(interval)
+        .bind(window)
+        .fetch_optional(pool)
+        .await?;
+
+        let (allowed, (full_at, now)) = match withdrawn {
+            Some(row) => (true, row),
+            None => (
+                false,
+                // the row may have been swept since, leaving a full bucket
+                sqlx::query_as::<_, (DateTime<Utc>, DateTime<Utc>)>(
+                    "select full_at, now() from rate_limit_buckets where key = $1",
+                )
+                .bind(&key)
+                .fetch_optional(pool)
+                .await?
+                .unwrap_or_default(),
+            ),
+        };
+        let backlog = (full_at - now).to_std().unwrap_or_default();
+        Ok(Quota::new(
+            self.capacity,
+            self.refill_interval,
+            allowed,
+            backlog,
+        ))
+    }
+
+    /// Fills the client's bucket back to capacity, or every bucket if `key` is `None`.
+    pub async fn refill(&self, key: Option<&str>) -> Result<(), RateLimitError> {
+        let pool = DB_POOL.get().ok_or(RateLimitError::NoDatabase)?;
+        match key {
+            Some(key) => {
+                sqlx::query("delete from rate_limit_buckets where key = $1")
+                    .bind(format!("{}:{key}", self.name))
+                    .execute(pool)
+                    .await?
+            }
+            None => {
+                sqlx::query("delete from rate_limit_buckets where left(key, length($1)) = $1")
+                    .bind(format!("{}:", self.name))
+                    .execute(pool)
+                    .await?
+            }
+        };
+        Ok(())
+    }
+
+    async fn sweep(&self, pool: &sqlx::PgPool) -> Result<(), sqlx::Error> {
+        {
+            let mut last_sweep = self
+                .last_sweep
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            if last_sweep.elapsed() < SWEEP_INTERVAL {
+                return Ok(());
+            }
+            *last_sweep = Instant::now();
+        }
+        sqlx::query("delete from rate_limit_buckets where full_at < now()")
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Where a [`RateLimiter`] keeps its buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// In this process, so every replica has its own buckets
+    Memory,
+    /// In the shared database
+    Postgres,
+}
+
+impl Backend {
+    /// Reads `RATE_LIMIT_BACKEND`, defaulting to [`Backend::Memory`].
+    pub fn from_env() -> Self {
+        match std::env::var(BACKEND_VAR).as_deref() {
+            Err(_) | Ok("memory") => Self::Memory,
+            Ok("postgres") => Self::Postgres,
+            Ok(value) => panic!("{BACKEND_VAR} is not valid: {value:?}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<Limiter<Arc<dyn Clock>>>>),
+    Postgres(Arc<PgLimiter>),
+}
+
+impl Store {
+    fn new(
+        backend: Backend,
+        name: &'static str,
+        capacity: u64,
+        refill_interval: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        match backend {
+            Backend::Memory => Self::Memory(Arc::new(Mutex::new(Limiter::new(
+                capacity,
+                refill_interval,
+                clock,
+            )))),
+            Backend::Postgres => {
+                Self::Postgres(Arc::new(PgLimiter::new(name, capacity, refill_interval)))
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RateLimitError {
+    #[error("rate limit query error: {0}")]
+    QueryError(#[from] sqlx::Error),
+
+    #[error("rate limit database is not connected")]
+    NoDatabase,
+}
+
+impl Scribe for RateLimitError {
+    fn render(self, res: &mut Response) {
+        match self {
+            Self::QueryError(_) | Self::NoDatabase => {
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+        res.render(Text::Plain(self.to_string()));
+    }
+}
+
+impl EndpointOutRegister for RateLimitError {
+    fn register(_components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        operation.responses.insert(
+            StatusCode::INTERNAL_SERVER_ERROR.as_str(),
+            salvo::oapi::Response::new("rate limit backend unavailable").add_content(
+                "text/plain",
+                Content::new(Schema::Object(Object::new().schema_type(BasicType::String))),
+            ),
+        );
+    }
+}
+
+/// Hoop rejecting requests with 429 once the client's bucket is empty. Clones share buckets, so
+/// a route can keep a handle to refill them.
+#[derive(Clone)]
+pub struct RateLimiter {
+    name: &'static str,
+    capacity: u64,
+    refill_interval: Duration,
+    backend: Backend,
+    clock: Arc<dyn Clock>,
+    store: Store,
+    key: KeyExtractor,
+    message: &'static str,
+}
+
+impl RateLimiter {
+    /// A limiter using the backend from [`Backend::from_env`]. `name` must be unique among
+    /// limiters, since buckets in the database are shared by all of them.
+    pub fn new(name: &'static str, capacity: u64, refill_interval: Duration) -> Self {
+        let backend = Backend::from_env();
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        Self {
+            name,
+            capacity,
+            refill_interval,
+            backend,
+            clock: clock.clone(),
+            store: Store::new(backend, name, capacity, refill_interval, clock),
+            key: client_key,
+            message: "Too many requests\n",
+        }
+    }
+
+    fn new_store(&mut self) {
+        self.store = Store::new(
+            self.backend,
+            self.name,
+            self.capacity,
+            self.refill_interval,
+            self.clock.clone(),
+        );
+    }
+
+    /// Where buckets are kept. Starts with fresh buckets.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self.new_store();
+        self
+    }
+
+    /// Time source for in-memory buckets, which Postgres ignores in favour of the database's
+    /// clock. Starts with fresh buckets.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.new_store();
+        self
+    }
+
+    /// How requests are grouped into buckets. Defaults to [`client_key`].
+    pub fn key(mut self, key: KeyExtractor) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Body of the 429 response.
+    pub fn message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// Fills the bucket with the given key back to capacity, or every bucket if `key` is
+    /// `None`. Keys are those made by the limiter's [`KeyExtractor`], such as `ip:127.0.0.1`.
+    pub async fn refill(&self, key: Option<&str>) -> Result<(), RateLimitError> {
+        match &self.store {
+            Store::Memory(limiter) => limiter
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .refill(key),
+            Store::Postgres(limiter) => limiter.refill(key).await?,
+        }
+        Ok(())
+    }
+
+    /// Takes one token from the bucket `req` counts against, for handlers charging more than the
+    /// hoop does.
+    pub async fn withdraw(&self, req: &Request) -> Result<Quota, RateLimitError> {
+        let key = (self.key)(req);
+        match &self.store {
+            Store::Memory(limiter) => Ok(limiter
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .withdraw(key)),
+            Store::Postgres(limiter) => limiter.withdraw(&key).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for RateLimiter {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let quota = match self.withdraw(req).await {
+            Ok(quota) => quota,
+            Err(err) => {
+                res.render(err);
+                ctrl.skip_rest();
+                return;
+            }
+        };
+        quota.write_headers(res);
+        if !quota.allowed {
+            res.status_code(StatusCode::TOO_MANY_REQUESTS);
+            res.render(Text::Plain(self.message));
+            ctrl.skip_rest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock that only moves when told to.
+    struct FakeClock(Mutex<Instant>);
+
+    impl FakeClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(Mutex::new(Instant::now())))
+        }
+
+        fn advance(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn limiter(capacity: u64) -> (Limiter<Arc<FakeClock>>, Arc<FakeClock>) {
+        let clock = FakeClock::new();
+        (Limiter::new(capacity, INTERVAL, clock.clone()), clock)
+    }
+
+    #[test]
+    fn allows_a_burst_up_to_capacity() {
+        let (mut limiter, _) = limiter(3);
+        for remaining in [2, 1, 0] {
+            let quota = limiter.withdraw("a".to_owned());
+            assert!(quota.allowed);
+            assert_eq!(quota.limit, 3);
+            assert_eq!(quota.remaining, remaining);
+            assert_eq!(quota.retry_after, Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn denies_once_the_bucket_is_empty() {
+        let (mut limiter, _) = limiter(3);
+        for _ in 0..3 {
+            limiter.withdraw("a".to_owned());
+        }
+        let quota = limiter.withdraw("a".to_owned());
+        assert!(!quota.allowed);
+        assert_eq!(quota.remaining, 0);
+        assert_eq!(quota.retry_after, INTERVAL);
+        assert_eq!(quota.reset, INTERVAL * 3);
+
+        // other clients have their own buckets
+        assert!(limiter.withdraw("b".to_owned()).allowed);
+    }
+
+    #[test]
+    fn retry_after_and_reset_count_down() {
+        let (mut limiter, clock) = limiter(3);
+        for _ in 0..3 {
+            limiter.withdraw("a".to_owned());
+        }
+        clock.advance(Duration::from_millis(40));
+        let quota = limiter.withdraw("a".to_owned());
+        assert!(!quota.allowed);
+        assert_eq!(quota.retry_after, Duration::from_millis(60));
+        assert_eq!(quota.reset, Duration::from_millis(260));
+    }
+
+    #[test]
+    fn refills_one_token_per_interval() {
+        let (mut limiter, clock) = limiter(3);
+        for _ in 0..3 {
+            limiter.withdraw("a".to_owned());
+        }
+        clock.advance(INTERVAL);
+        let quota = limiter.withdraw("a".to_owned());
+        assert!(quota.allowed);
+        assert_eq!(quota.remaining, 0);
+        assert!(!limiter.withdraw("a".to_owned()).allowed);
+
+        clock.advance(INTERVAL * 3);
+        assert_eq!(limiter.withdraw("a".to_owned()).remaining, 2);
+    }
+
+    #[test]
+    fn refill_fills_the_bucket() {
+        let (mut limiter, _) = limiter(1);
+        limiter.withdraw("a".to_owned());
+        limiter.withdraw("b".to_owned());
+        limiter.refill(Some("a"));
+        assert!(limiter.withdraw("a".to_owned()).allowed);
+        assert!(!limiter.withdraw("b".to_owned()).allowed);
+        limiter.refill(None);
+        assert!(limiter.withdraw("b".to_owned()).allowed);
+    }
+
+    #[test]
+    fn parses_forwarded_addresses() {
+        let v4 = "192.0.2.1".parse::<IpAddr>().ok();
+        let v6 = "2001:db8::1".parse::<IpAddr>().ok();
+        assert_eq!(parse_addr(" 192.0.2.1"), v4);
+        assert_eq!(parse_addr("192.0.2.1:4711"), v4);
+        assert_eq!(parse_addr("2001:db8::1"), v6);
+        assert_eq!(parse_addr("\"[2001:db8::1]:4711\""), v6);
+        assert_eq!(parse_addr("[2001:db8::1]"), v6);
+        assert_eq!(parse_addr("unknown"), None);
+
+        assert_eq!(
+            forwarded_for(" proto=https;For=\"[2001:db8::1]\""),
+            Some("\"[2001:db8::1]\"")
+        );
+        assert_eq!(forwarded_for("by=192.0.2.2"), None);
+    }
+
+    #[test]
+    fn zero_capacity_denies_everything() {
+        let (mut limiter, clock) = limiter(0);
+        let quota = limiter.withdraw("a".to_owned());
+        assert!(!quota.allowed);
+        assert_eq!(quota.limit, 0);
+        assert_eq!(quota.remaining, 0);
+        assert_eq!(quota.reset, Duration::ZERO);
+        clock.advance(INTERVAL * 10);
+        assert!(!limiter.withdraw("a".to_owned()).allowed);
+    }
+}